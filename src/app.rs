@@ -1,4 +1,8 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, TryRecvError};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use image::ImageFormat;
 
@@ -6,9 +10,59 @@ use eframe::egui::{self, Color32, Key};
 
 use std::net::Ipv4Addr;
 
-use crate::streaming::Streaming;
+use crate::streaming::event::{StreamingEvent, StreamingStats, Subscription};
+use crate::streaming::frame::FrameFormat;
+use crate::streaming::blank::BlankContent;
+use crate::streaming::capabilities::{Capabilities, Feature};
+use crate::streaming::recording::{RecordingOptions, Segmentation};
+use crate::streaming::player::{discover_durations, list_recordings, Player, RecordingInfo};
+use crate::streaming::pipeline::{ClientConfig, OutputSettings, ServerConfig, VideoEncoder, MAX_FRAMERATE, MIN_FRAMERATE};
+use crate::streaming::simulcast::SimulcastLayer;
+use crate::streaming::snapshot::SnapshotFormat;
+use crate::streaming::source::{CaptureSource, Monitor, SourceKind};
+use crate::streaming::watermark::{Watermark, WatermarkContent, WatermarkKind, WatermarkPosition, WatermarkSettings};
+use crate::streaming::{self, Streaming};
 use winit::event_loop::EventLoop;
 
+mod annotator;
+mod area_selector;
+mod mask_editor;
+
+use annotator::Annotator;
+use area_selector::{area_editor, AreaSelector, Selection};
+use mask_editor::MaskEditor;
+
+/// Heights the stream can be scaled to
+const OUTPUT_HEIGHTS: [u32; 4] = [1080, 720, 480, 360];
+
+const MEGABYTE: u64 = 1024 * 1024;
+const GIGABYTE: u64 = 1024 * MEGABYTE;
+
+/// Checkbox enabling a limit, with its value. Returns true if it changed
+fn optional_limit(ui: &mut egui::Ui, label: &str, value: &mut Option<u64>, default: u64, suffix: &str) -> bool {
+    ui.horizontal(|ui| {
+        let mut enabled = value.is_some();
+        let mut changed = ui.checkbox(&mut enabled, label).changed();
+        if changed {
+            *value = enabled.then_some(default);
+        }
+        if let Some(value) = value {
+            changed |= ui.add(egui::DragValue::new(value).clamp_range(1..=u64::MAX).suffix(suffix)).changed();
+        }
+        changed
+    }).inner
+}
+
+/// `mm:ss`, or `h:mm:ss` for longer durations
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
 fn is_valid_ipv4(ip: &str) -> bool {
     ip.parse::<Ipv4Addr>().is_ok()
 }
@@ -17,6 +71,7 @@ fn is_valid_ipv4(ip: &str) -> bool {
 enum Mode {
     Caster,
     Receiver,
+    Recordings,
 }
 
 impl Default for Mode {
@@ -38,7 +93,8 @@ impl Default for TransmissionStatus {
     }
 }
 
-#[derive(PartialEq, Clone)]
+/// A rectangle of the captured monitor, in pixels, the end is excluded
+#[derive(PartialEq, Clone, Copy, Debug)]
 struct ScreenArea {
     startx: u32,
     starty: u32,
@@ -46,6 +102,25 @@ struct ScreenArea {
     endy: u32,
 }
 
+impl ScreenArea {
+    fn from_corners((x1, y1): (u32, u32), (x2, y2): (u32, u32)) -> Self {
+        ScreenArea {
+            startx: x1.min(x2),
+            starty: y1.min(y2),
+            endx: x1.max(x2),
+            endy: y1.max(y2),
+        }
+    }
+
+    fn width(&self) -> u32 {
+        self.endx - self.startx
+    }
+
+    fn height(&self) -> u32 {
+        self.endy - self.starty
+    }
+}
+
 pub struct MyApp {
     _streaming: Option<Streaming>,
     current_image: Arc<Mutex<Option<egui::ColorImage>>>,
@@ -57,13 +132,49 @@ pub struct MyApp {
     pause: bool,
     error_msg: Option<String>,
     blanking_screen: bool,
-    slider_value1: f32,
-    slider_value2: f32,
-    slider_value3: f32,
-    slider_value4: f32,
     screen_width: u32,
     screen_height: u32,
-
+    events: Arc<Mutex<Vec<StreamingEvent>>>,
+    /// Connected receivers with their simulcast layer
+    receivers: Vec<(String, usize)>,
+    /// Send every layer of `SimulcastLayer::DEFAULTS`
+    simulcast: bool,
+    stats: Option<StreamingStats>,
+    recording: Option<PathBuf>,
+    recording_options: RecordingOptions,
+    capabilities: Option<Capabilities>,
+    encoder: VideoEncoder,
+    source_kind: SourceKind,
+    /// File, folder, device or pattern, depending on `source_kind`
+    source_path: String,
+    /// Windows that can be cast, (id, description)
+    windows: Vec<(u64, String)>,
+    selected_window: Option<u64>,
+    /// Monitors with their names
+    monitors: Vec<(Monitor, String)>,
+    selected_monitor: usize,
+    /// Overlay to drag the capture area, open while selecting
+    area_selector: Option<AreaSelector>,
+    /// Size and framerate of the stream, can be changed while casting
+    output: OutputSettings,
+    /// Files in the recordings folder, their durations are filled in by another thread
+    recordings: Arc<Mutex<Vec<RecordingInfo>>>,
+    /// The recording open in the player, with its path
+    player: Option<(Player, PathBuf)>,
+    /// Capture source chosen in another thread, e.g. in the screen sharing dialog of the portal
+    pending_source: Option<Receiver<Result<CaptureSource, String>>>,
+    snapshot_format: SnapshotFormat,
+    /// Seconds between periodic snapshots, `None` to take them only by hand
+    snapshot_interval: Option<u64>,
+    next_snapshot: Option<Instant>,
+    /// The last snapshot saved
+    snapshot: Option<PathBuf>,
+    annotator: Annotator,
+    watermarks: WatermarkSettings,
+    /// Kept between casts
+    mask_editor: MaskEditor,
+    /// What receivers see while the screen is blanked
+    blank_content: BlankContent,
 }
 
 impl MyApp {
@@ -71,22 +182,58 @@ impl MyApp {
         let event_loop = EventLoop::new();
 
         // Get monitor dimensions
-        let primary_monitor = event_loop.available_monitors().next().expect("No monitors available");
-        let video_mode = primary_monitor
-            .video_modes()
-            .next()
-            .expect("No video modes available");
-        let screen_width = video_mode.size().width;
-        let screen_height = video_mode.size().height;
+        let monitors: Vec<(Monitor, String)> = event_loop
+            .available_monitors()
+            .enumerate()
+            .map(|(i, m)| {
+                #[cfg(target_os = "windows")]
+                let handle = {
+                    use winit::platform::windows::MonitorHandleExtWindows;
+                    m.hmonitor() as u64
+                };
+                #[cfg(not(target_os = "windows"))]
+                let handle = 0;
+                let monitor = Monitor {
+                    index: i as u32,
+                    handle,
+                    x: m.position().x,
+                    y: m.position().y,
+                    width: m.size().width,
+                    height: m.size().height,
+                };
+                let name = m.name().unwrap_or_else(|| format!("Monitor {}", i + 1));
+                (monitor, format!("{} ({}x{})", name, monitor.width, monitor.height))
+            })
+            .collect();
+        let primary_monitor = monitors.first().expect("No monitors available").0;
+        let screen_width = primary_monitor.width;
+        let screen_height = primary_monitor.height;
 
+        let (capabilities, error_msg) = match streaming::capabilities() {
+            Ok(c) => (Some(c), None),
+            Err(e) => (None, Some(format!("GStreamer is not available: {}", e))),
+        };
+        let encoder = capabilities
+            .as_ref()
+            .and_then(|c| c.encoders().first().copied())
+            .unwrap_or_default();
 
+        // X11 capture only shows black frames on Wayland, the portal is the way to go there
+        #[cfg(target_os = "linux")]
+        let source_kind = if streaming::portal::is_wayland_session()
+            && capabilities.as_ref().is_some_and(|c| c.sources().contains(&SourceKind::PipeWire))
+        {
+            SourceKind::PipeWire
+        } else {
+            SourceKind::Screen
+        };
+        #[cfg(not(target_os = "linux"))]
+        let source_kind = SourceKind::Screen;
 
         let current_image = Arc::new(Mutex::new(Some(egui::ColorImage::new(
             [200, 200],
             Color32::BLACK,
         ))));
-        
-        
 
         Self {
             _streaming: None,
@@ -97,21 +244,781 @@ impl MyApp {
             selected_screen_area: None,
             transmission_status: TransmissionStatus::default(),
             pause: false,
-            error_msg: None,
+            error_msg,
             blanking_screen: false,
-            slider_value1: 0.0,
-            slider_value2: 0.0,
-            slider_value3: 0.0,
-            slider_value4: 0.0,
             screen_width: screen_width,
             screen_height: screen_height,
+            events: Arc::new(Mutex::new(Vec::new())),
+            receivers: Vec::new(),
+            simulcast: false,
+            stats: None,
+            recording: None,
+            recording_options: RecordingOptions::default(),
+            capabilities,
+            encoder,
+            source_kind,
+            source_path: String::default(),
+            windows: Vec::new(),
+            selected_window: None,
+            monitors,
+            selected_monitor: 0,
+            area_selector: None,
+            output: OutputSettings::default(),
+            recordings: Arc::new(Mutex::new(Vec::new())),
+            player: None,
+            pending_source: None,
+            snapshot_format: SnapshotFormat::default(),
+            snapshot_interval: None,
+            next_snapshot: None,
+            snapshot: None,
+            annotator: Annotator::default(),
+            watermarks: WatermarkSettings::default(),
+            mask_editor: MaskEditor::default(),
+            blank_content: BlankContent::default(),
+        }
+    }
+
+    fn select_monitor(&mut self, index: usize) {
+        self.selected_monitor = index;
+        let monitor = self.monitors[index].0;
+        self.screen_width = monitor.width;
+        self.screen_height = monitor.height;
+        self.selected_screen_area = None;
+    }
+
+    fn open_area_selector(&mut self) {
+        #[cfg(target_os = "linux")]
+        let windows: Vec<_> = streaming::window::list_windows()
+            .map(|windows| windows.into_iter().map(|w| (w.x, w.y, w.width, w.height)).collect())
+            .unwrap_or_default();
+        #[cfg(not(target_os = "linux"))]
+        let windows = Vec::new();
+
+        let monitor = self.monitors[self.selected_monitor].0;
+        self.area_selector = Some(AreaSelector::new(monitor, self.selected_screen_area, &windows));
+    }
+
+    #[cfg(target_os = "linux")]
+    fn refresh_windows(&mut self) {
+        match streaming::window::list_windows() {
+            Ok(windows) => {
+                self.windows = windows
+                    .into_iter()
+                    .map(|w| (w.id, format!("{} ({}x{})", w.title, w.width, w.height)))
+                    .collect();
+            }
+            Err(e) => self.error_msg = Some(e.to_string()),
+        }
+        if !self.windows.iter().any(|(id, _)| Some(*id) == self.selected_window) {
+            self.selected_window = None;
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn refresh_windows(&mut self) {}
+
+    fn capture_source(&self) -> Result<CaptureSource, String> {
+        let path = self.source_path.trim();
+        match self.source_kind {
+            SourceKind::Screen => Ok(CaptureSource::Screen {
+                monitor: (self.monitors.len() > 1).then(|| self.monitors[self.selected_monitor].0),
+            }),
+            SourceKind::Window => self
+                .selected_window
+                .map(|id| CaptureSource::Window { id })
+                .ok_or_else(|| "Please select a window!".to_string()),
+            #[cfg(target_os = "linux")]
+            SourceKind::PipeWire => {
+                use streaming::portal::{MockPortal, ScreenCastPortal};
+                // a node id skips the portal, e.g. to cast a `pipewiresink` test stream,
+                // otherwise `choose_source` opens it
+                let node_id = path.parse().map_err(|_| format!("Invalid PipeWire node id: {}", path))?;
+                MockPortal { node_id }.open().map(CaptureSource::PipeWire).map_err(|e| e.to_string())
+            }
+            #[cfg(not(target_os = "linux"))]
+            SourceKind::PipeWire => Err("PipeWire capture is only available on Linux".to_string()),
+            SourceKind::Camera => Ok(CaptureSource::Camera {
+                device: (!path.is_empty()).then(|| path.to_string()),
+            }),
+            SourceKind::File => {
+                if path.is_empty() {
+                    Err("Please insert the path of a video file!".to_string())
+                } else {
+                    Ok(CaptureSource::File(PathBuf::from(path)))
+                }
+            }
+            SourceKind::TestPattern => Ok(CaptureSource::TestPattern(
+                if path.is_empty() { "smpte" } else { path }.to_string(),
+            )),
+            SourceKind::Slideshow => {
+                let mut images: Vec<PathBuf> = std::fs::read_dir(path)
+                    .map_err(|e| format!("Can't read {}: {}", path, e))?
+                    .filter_map(|entry| entry.ok().map(|e| e.path()))
+                    .filter(|p| {
+                        p.extension()
+                            .and_then(|ext| ext.to_str())
+                            .is_some_and(|ext| ["png", "jpg", "jpeg"].contains(&ext.to_lowercase().as_str()))
+                    })
+                    .collect();
+                images.sort();
+                if images.is_empty() {
+                    return Err(format!("No images found in {}", path));
+                }
+                Ok(CaptureSource::Slideshow {
+                    images,
+                    interval: std::time::Duration::from_secs(5),
+                })
+            }
+        }
+    }
+
+    /// Starts casting once the capture source is known. The portal may wait for the user,
+    /// so it's opened in another thread and `poll_pending_source` takes over from there
+    fn choose_source(&mut self, ctx: &egui::Context) {
+        #[cfg(target_os = "linux")]
+        if self.source_kind == SourceKind::PipeWire && self.source_path.trim().is_empty() {
+            use streaming::portal::{DesktopPortal, ScreenCastPortal};
+            let (tx, rx) = std::sync::mpsc::channel();
+            let ctx = ctx.clone();
+            thread::spawn(move || {
+                let source = DesktopPortal.open().map(CaptureSource::PipeWire).map_err(|e| e.to_string());
+                let _ = tx.send(source);
+                ctx.request_repaint();
+            });
+            self.error_msg.take();
+            self.pending_source = Some(rx);
+            return;
+        }
+
+        match self.capture_source() {
+            Ok(source) => self.start_casting(source, ctx),
+            Err(e) => self.error_msg = Some(e),
+        }
+    }
+
+    /// Starts casting the source chosen in another thread, if it's ready
+    fn poll_pending_source(&mut self, ctx: &egui::Context) {
+        let Some(rx) = &self.pending_source else {
+            return;
+        };
+        let source = match rx.try_recv() {
+            Ok(source) => source,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => Err("The capture source was not chosen".to_string()),
+        };
+        self.pending_source = None;
+        match source {
+            Ok(source) => self.start_casting(source, ctx),
+            Err(e) => self.error_msg = Some(e),
+        }
+    }
+
+    fn start_casting(&mut self, source: CaptureSource, ctx: &egui::Context) {
+        if !matches!(self._streaming, Some(Streaming::Server(_))) {
+            match Streaming::new_server(self.server_config(source)) {
+                Ok(s) => {
+                    self.listen_events(s.subscribe(), ctx);
+                    self._streaming = Some(s);
+                }
+                Err(e) => {
+                    self.error_msg = Some(e.to_string());
+                }
+            }
+        }
+        if let Some(s) = &self._streaming {
+            self.pause = false;
+            self.blanking_screen = false;
+            self.error_msg.take();
+            match s.start() {
+                Ok(_) => {
+                    self.send_masks();
+                    self.transmission_status = TransmissionStatus::Casting;
+                }
+                Err(e) => {
+                    self.error_msg = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    fn server_config(&self, source: CaptureSource) -> ServerConfig {
+        ServerConfig {
+            frame_format: FrameFormat::Rgba,
+            encoder: self.encoder,
+            source,
+            output: self.output,
+            layers: if self.simulcast { SimulcastLayer::DEFAULTS.to_vec() } else { Vec::new() },
+            default_layer: 0,
+            watermarks: self.watermarks.clone(),
+            blank: self.blank_content.clone(),
+        }
+    }
+
+    /// Shows `blank_content` to the receivers
+    /// Joins the caster at `caster_address`, recording the stream from the start if `record`
+    fn start_receiving(&mut self, record: bool, ctx: &egui::Context) {
+        if !is_valid_ipv4(&self.caster_address) {
+            self.error_msg = Some("Please insert a valid IP address!".to_string());
+            return;
+        }
+        match Streaming::new_client(&self.caster_address, ClientConfig {
+            frame_format: FrameFormat::Rgba,
+        }) {
+            Ok(s) => {
+                self.listen_events(s.subscribe(), ctx);
+                self._streaming = Some(s);
+            }
+            Err(e) => {
+                self.error_msg = Some(e.to_string());
+            }
+        }
+        if let Some(s) = &self._streaming {
+            self.error_msg.take();
+            match s.start() {
+                Ok(_) => {
+                    self.transmission_status = TransmissionStatus::Receiving;
+                    if record {
+                        self.start_recording();
+                    }
+                }
+                Err(e) => {
+                    self.error_msg = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    fn blank_screen(&mut self) {
+        if let Some(Streaming::Server(s)) = &self._streaming {
+            if let Err(e) = s.blank_screen_with(self.blank_content.clone()) {
+                self.error_msg = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Content of the blank screen, applied right away while blanked
+    fn blank_content_ui(&mut self, ui: &mut egui::Ui) {
+        let previous = self.blank_content.clone();
+        ui.horizontal(|ui| {
+            ui.label("Blank screen:");
+            egui::ComboBox::from_id_source("blank_content")
+                .selected_text(self.blank_content.to_string())
+                .show_ui(ui, |ui| {
+                    let choices = [
+                        BlankContent::default(),
+                        BlankContent::Text("Be right back".to_string()),
+                        BlankContent::FrozenFrame,
+                    ];
+                    for choice in choices {
+                        let selected = std::mem::discriminant(&choice) == std::mem::discriminant(&self.blank_content);
+                        if ui.selectable_label(selected, choice.to_string()).clicked() && !selected {
+                            self.blank_content = choice;
+                        }
+                    }
+                    if ui.selectable_label(matches!(self.blank_content, BlankContent::Image(_)), "Image...").clicked() {
+                        if let Some(path) = rfd::FileDialog::new().add_filter("Images", &["png", "jpg", "jpeg"]).pick_file() {
+                            self.blank_content = BlankContent::Image(path);
+                        }
+                    }
+                });
+            match &mut self.blank_content {
+                BlankContent::Color(color) => {
+                    ui.color_edit_button_srgb(color);
+                }
+                BlankContent::Text(text) => {
+                    ui.text_edit_singleline(text);
+                }
+                BlankContent::Image(path) => {
+                    ui.label(path.file_name().unwrap_or_default().to_string_lossy());
+                }
+                BlankContent::FrozenFrame => {}
+            }
+        });
+        if self.blanking_screen && self.blank_content != previous {
+            self.blank_screen();
+        }
+    }
+
+    /// Watermarks of the next cast, they can't be changed while casting
+    fn watermarks_ui(&mut self, ui: &mut egui::Ui) {
+        let kinds = self.capabilities.as_ref().map(|c| c.watermarks()).unwrap_or_default();
+        let caster_name = self.caster_name();
+        ui.collapsing("Watermarks", |ui| {
+            let mut remove = None;
+            for (i, watermark) in self.watermarks.watermarks.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    match &mut watermark.content {
+                        WatermarkContent::Text(text) => {
+                            ui.text_edit_singleline(text);
+                        }
+                        WatermarkContent::Clock => {
+                            ui.label("Date and time");
+                        }
+                        WatermarkContent::Image(path) => {
+                            ui.label(path.file_name().unwrap_or_default().to_string_lossy());
+                        }
+                    }
+                    egui::ComboBox::from_id_source(("watermark_position", i))
+                        .selected_text(watermark.position.to_string())
+                        .show_ui(ui, |ui| {
+                            for position in WatermarkPosition::ALL {
+                                ui.selectable_value(&mut watermark.position, position, position.to_string());
+                            }
+                        });
+                    ui.add(egui::Slider::new(&mut watermark.opacity, 0.0..=1.0).text("Opacity"));
+                    if ui.button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = remove {
+                self.watermarks.watermarks.remove(i);
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Add:");
+                for kind in kinds {
+                    if ui.button(kind.to_string()).clicked() {
+                        let content = match kind {
+                            WatermarkKind::Text => Some(WatermarkContent::Text(caster_name.clone())),
+                            WatermarkKind::Clock => Some(WatermarkContent::Clock),
+                            WatermarkKind::Image => rfd::FileDialog::new()
+                                .add_filter("Images", &["png", "jpg", "jpeg", "svg"])
+                                .pick_file()
+                                .map(WatermarkContent::Image),
+                        };
+                        if let Some(content) = content {
+                            self.watermarks.watermarks.push(Watermark::new(content));
+                        }
+                    }
+                }
+            });
+            ui.checkbox(&mut self.watermarks.recordings_only, "Only in recordings")
+                .on_hover_text("Receivers get the stream without watermarks");
+        });
+    }
+
+    /// Consumes the events of a new streaming session in a background thread: frames are
+    /// decoded there, everything else is handed to the ui through `self.events`
+    fn listen_events(&mut self, rx: Subscription, ctx: &egui::Context) {
+        self.events = Arc::new(Mutex::new(Vec::new()));
+        let events = self.events.clone();
+        let image_clone = self.current_image.clone();
+        let ctx = ctx.clone();
+
+        thread::spawn(move || {
+            for event in rx {
+                match event {
+                    StreamingEvent::FrameReady(frame) => {
+                        let image = match frame.format {
+                            FrameFormat::Jpeg => {
+                                let image = match image::load_from_memory_with_format(&frame.data, ImageFormat::Jpeg) {
+                                    Ok(image) => image.to_rgba8(),
+                                    Err(_) => continue,
+                                };
+
+                                let size = [image.width() as usize, image.height() as usize];
+                                egui::ColorImage::from_rgba_premultiplied(size, &image)
+                            }
+                            FrameFormat::Rgba => {
+                                let size = [frame.width as usize, frame.height as usize];
+                                egui::ColorImage::from_rgba_premultiplied(size, &frame.packed_rgba())
+                            }
+                        };
+
+                        *image_clone.lock().unwrap() = Some(image);
+                    }
+                    event => events.lock().unwrap().push(event),
+                }
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    fn handle_event(&mut self, event: StreamingEvent) {
+        match event {
+            StreamingEvent::FrameReady(_) => { /* frames are decoded by the listener thread */ }
+            StreamingEvent::ReceiverJoined(ip) => self.receivers.push((ip, 0)),
+            StreamingEvent::ReceiverLeft(ip) => {
+                if let Some(i) = self.receivers.iter().position(|(r, _)| *r == ip) {
+                    self.receivers.remove(i);
+                }
+            }
+            StreamingEvent::ReceiverLayerChanged(ip, layer) => {
+                if let Some((_, l)) = self.receivers.iter_mut().find(|(r, _)| *r == ip) {
+                    *l = layer;
+                }
+            }
+            StreamingEvent::Disconnected => {
+                if self.transmission_status == TransmissionStatus::Receiving {
+                    self.caster_address = String::default();
+                    self.stop_streaming();
+                }
+            }
+            StreamingEvent::Error(e) => {
+                self.error_msg = Some(e.to_string());
+                self.close_player();
+                if self.transmission_status == TransmissionStatus::Receiving {
+                    self.caster_address = String::default();
+                }
+                self.stop_streaming();
+            }
+            StreamingEvent::Warning(e) => self.error_msg = Some(format!("Warning: {}", e)),
+            StreamingEvent::StatsUpdated(stats) => self.stats = Some(stats),
+            StreamingEvent::RecordingStarted(path) => self.recording = Some(path),
+            StreamingEvent::RecordingFinished(_) => self.recording = None,
+        }
+    }
+
+    /// Name used for `{caster}` in the recording file names
+    fn caster_name(&self) -> String {
+        match self.mode {
+            Mode::Receiver => self.caster_address.clone(),
+            Mode::Caster | Mode::Recordings => std::env::var("HOSTNAME")
+                .or_else(|_| std::env::var("COMPUTERNAME"))
+                .unwrap_or_else(|_| "caster".to_string()),
+        }
+    }
+
+    fn start_recording(&mut self) {
+        if let Some(s) = &self._streaming {
+            let options = &self.recording_options;
+            let path = options.path(&self.caster_name());
+            let result = std::fs::create_dir_all(&options.directory)
+                .map_err(|e| format!("Can't create {}: {}", options.directory.display(), e))
+                .and_then(|_| s.start_recording(&path, options).map_err(|e| e.to_string()));
+            if let Err(e) = result {
+                self.error_msg = Some(e);
+            }
+        }
+    }
+
+    /// Record toggle of the Casting and Receiving panels
+    fn recording_ui(&mut self, ui: &mut egui::Ui) {
+        match &self.recording {
+            Some(path) => {
+                ui.horizontal(|ui| {
+                    ui.colored_label(egui::Color32::LIGHT_RED, format!("Recording to {}", path.display()));
+                    if ui.button("Stop recording").clicked() {
+                        if let Some(s) = &self._streaming {
+                            s.stop_recording();
+                        }
+                    }
+                });
+            }
+            None => {
+                self.recording_options_ui(ui);
+                let container = self.recording_options.container;
+                let can_record = self.capabilities.as_ref()
+                    .is_some_and(|c| !c.is_missing(Feature::Record) && c.containers().contains(&container));
+                if ui.add_enabled(can_record, egui::Button::new("Record")).clicked() {
+                    self.start_recording();
+                }
+            }
+        }
+    }
+
+    fn recording_options_ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Recording options", |ui| {
+            let options = &mut self.recording_options;
+            ui.horizontal(|ui| {
+                ui.label(format!("Folder: {}", options.directory.display()));
+                if ui.button("Choose...").clicked() {
+                    if let Some(directory) = rfd::FileDialog::new().set_directory(&options.directory).pick_folder() {
+                        options.directory = directory;
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("File name:");
+                ui.text_edit_singleline(&mut options.pattern)
+                    .on_hover_text("{caster} is the caster name, {counter} a progressive number,\n%Y, %m, %d, %H, %M, %S the current date and time");
+            });
+            ui.horizontal(|ui| {
+                ui.label("Format:");
+                let containers = self.capabilities.as_ref().map(|c| c.containers()).unwrap_or_default();
+                egui::ComboBox::from_id_source("container")
+                    .selected_text(options.container.to_string())
+                    .show_ui(ui, |ui| {
+                        for container in containers {
+                            ui.selectable_value(&mut options.container, container, container.to_string());
+                        }
+                    });
+            });
+            let can_segment = self.capabilities.as_ref().is_some_and(|c| c.can_segment());
+            let mut segmented = options.segmentation.is_some();
+            if ui.add_enabled(can_segment, egui::Checkbox::new(&mut segmented, "Split in segments")).changed() {
+                options.segmentation = segmented.then(Segmentation::default);
+            }
+            if let Some(segmentation) = &mut options.segmentation {
+                let mut minutes = segmentation.max_duration.map(|d| d.as_secs() / 60);
+                if optional_limit(ui, "Max length", &mut minutes, 30, " min") {
+                    segmentation.max_duration = minutes.map(|m| std::time::Duration::from_secs(m.max(1) * 60));
+                }
+                let mut megabytes = segmentation.max_size.map(|b| b / MEGABYTE);
+                if optional_limit(ui, "Max size", &mut megabytes, 1024, " MB") {
+                    segmentation.max_size = megabytes.map(|mb| mb.max(1) * MEGABYTE);
+                }
+                let mut gigabytes = segmentation.retention.map(|b| b / GIGABYTE);
+                if optional_limit(ui, "Keep only the last", &mut gigabytes, 10, " GB") {
+                    segmentation.retention = gigabytes.map(|gb| gb.max(1) * GIGABYTE);
+                }
+            }
+        });
+    }
+
+    fn send_masks(&self) {
+        if let Some(Streaming::Server(s)) = &self._streaming {
+            s.set_privacy_masks(self.mask_editor.masks());
+        }
+    }
+
+    /// Masks a window where it is now, if it is in the captured area
+    #[cfg(target_os = "linux")]
+    fn mask_window(&mut self, id: u64) {
+        let window = match streaming::window::list_windows() {
+            Ok(windows) => windows.into_iter().find(|w| w.id == id),
+            Err(e) => {
+                self.error_msg = Some(e.to_string());
+                return;
+            }
+        };
+        let Some(window) = window else {
+            return;
+        };
+
+        let monitor = self.monitors[self.selected_monitor].0;
+        let area = self.selected_screen_area.unwrap_or(ScreenArea {
+            startx: 0,
+            starty: 0,
+            endx: monitor.width,
+            endy: monitor.height,
+        });
+        let (left, top) = (monitor.x + area.startx as i32, monitor.y + area.starty as i32);
+        let relative = |v: i32, origin: i32, size: u32| ((v - origin) as f32 / size as f32).clamp(0.0, 1.0);
+        let from = (relative(window.x, left, area.width()), relative(window.y, top, area.height()));
+        let to = (
+            relative(window.x + window.width as i32, left, area.width()),
+            relative(window.y + window.height as i32, top, area.height()),
+        );
+        if from.0 < to.0 && from.1 < to.1 {
+            self.mask_editor.add(from, to);
+            self.send_masks();
+        } else {
+            self.error_msg = Some(format!("{} is outside the captured area", window.title));
+        }
+    }
+
+    fn send_annotations(&self) {
+        if let Some(Streaming::Server(s)) = &self._streaming {
+            s.set_annotations(self.annotator.annotations());
+        }
+    }
+
+    /// Saves the current frame next to the recordings
+    fn take_snapshot(&mut self) {
+        if let Some(s) = &self._streaming {
+            match s.snapshot(&self.recording_options.directory, self.snapshot_format) {
+                Ok(path) => self.snapshot = Some(path),
+                Err(e) => self.error_msg = Some(format!("Can't save the snapshot: {}", e)),
+            }
+        }
+    }
+
+    /// Snapshot button of the Casting and Receiving panels, also takes the periodic snapshots
+    fn snapshot_ui(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let hotkey = ctx.input(|i| i.key_pressed(Key::S) && i.modifiers.ctrl);
+        ui.horizontal(|ui| {
+            if ui.button("Snapshot").on_hover_text("Ctrl + S").clicked() || hotkey {
+                self.take_snapshot();
+            }
+            egui::ComboBox::from_id_source("snapshot_format")
+                .selected_text(self.snapshot_format.to_string())
+                .show_ui(ui, |ui| {
+                    for format in SnapshotFormat::ALL {
+                        ui.selectable_value(&mut self.snapshot_format, format, format.to_string());
+                    }
+                });
+            if optional_limit(ui, "Every", &mut self.snapshot_interval, 60, " s") {
+                self.next_snapshot = None;
+            }
+        });
+        if let Some(path) = &self.snapshot {
+            ui.label(format!("Snapshot saved to {}", path.display()));
+        }
+
+        if let Some(interval) = self.snapshot_interval {
+            let now = Instant::now();
+            match self.next_snapshot {
+                Some(next) if now < next => ctx.request_repaint_after(next - now),
+                next => {
+                    if next.is_some() {
+                        self.take_snapshot();
+                    }
+                    let interval = Duration::from_secs(interval);
+                    self.next_snapshot = Some(now + interval);
+                    ctx.request_repaint_after(interval);
+                }
+            }
+        }
+    }
+
+    fn refresh_recordings(&mut self, ctx: &egui::Context) {
+        let recordings = match list_recordings(&self.recording_options.directory) {
+            Ok(recordings) => recordings,
+            Err(e) => {
+                self.recordings.lock().unwrap().clear();
+                self.error_msg = Some(format!("Can't list the recordings: {}", e));
+                return;
+            }
+        };
+        let paths = recordings.iter().map(|r| r.path.clone()).collect();
+        *self.recordings.lock().unwrap() = recordings;
+
+        let recordings = self.recordings.clone();
+        let ctx = ctx.clone();
+        let discovered = discover_durations(paths, move |path, duration| {
+            if let Some(recording) = recordings.lock().unwrap().iter_mut().find(|r| r.path == path) {
+                recording.duration = Some(duration);
+            }
+            ctx.request_repaint();
+        });
+        if let Err(e) = discovered {
+            self.error_msg = Some(format!("Can't read the recordings: {}", e));
+        }
+    }
+
+    fn open_recording(&mut self, path: PathBuf, ctx: &egui::Context) {
+        self.close_player();
+        match Player::new(&path, FrameFormat::Rgba) {
+            Ok(player) => {
+                self.error_msg.take();
+                self.listen_events(player.subscribe(), ctx);
+                self.player = Some((player, path));
+            }
+            Err(e) => self.error_msg = Some(format!("Can't play {}: {}", path.display(), e)),
+        }
+    }
 
+    fn close_player(&mut self) {
+        if self.player.take().is_some() {
+            self.current_image = Arc::new(Mutex::new(Some(egui::ColorImage::new(
+                [200, 200],
+                Color32::BLACK))));
         }
     }
+
+    /// List of the saved recordings and controls of the player
+    fn recordings_ui(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let can_play = self.capabilities.as_ref().is_some_and(|c| c.can_play());
+
+        ui.horizontal(|ui| {
+            ui.label(format!("Folder: {}", self.recording_options.directory.display()));
+            if ui.button("Choose...").clicked() {
+                if let Some(directory) = rfd::FileDialog::new().set_directory(&self.recording_options.directory).pick_folder() {
+                    self.recording_options.directory = directory;
+                    self.refresh_recordings(ctx);
+                }
+            }
+            if ui.button("Refresh").clicked() {
+                self.refresh_recordings(ctx);
+            }
+        });
+
+        let recordings = self.recordings.lock().unwrap().clone();
+        if recordings.is_empty() {
+            ui.label("No recordings in this folder");
+        }
+        let mut open = None;
+        egui::ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+            egui::Grid::new("recordings").striped(true).show(ui, |ui| {
+                for recording in &recordings {
+                    let name = recording.path.file_name().unwrap_or_default().to_string_lossy();
+                    let date = recording.modified
+                        .map(|m| chrono::DateTime::<chrono::Local>::from(m).format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_default();
+                    let duration = recording.duration.map_or("--:--".to_string(), format_duration);
+                    ui.label(name);
+                    ui.label(date);
+                    ui.label(duration);
+                    ui.label(format!("{:.1} MB", recording.size as f64 / MEGABYTE as f64));
+                    if ui.add_enabled(can_play, egui::Button::new("Play")).clicked() {
+                        open = Some(recording.path.clone());
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+        if let Some(path) = open {
+            self.open_recording(path, ctx);
+        }
+
+        let mut close = false;
+        if let Some((player, path)) = &self.player {
+            ui.separator();
+            ui.label(path.file_name().unwrap_or_default().to_string_lossy());
+
+            let position = player.position().unwrap_or_default();
+            let duration = player.duration().unwrap_or_default();
+            let input = ctx.input(|i| i.clone());
+            let mut result = Ok(());
+            ui.horizontal(|ui| {
+                if player.is_playing() {
+                    if ui.button("Pause").on_hover_text("Space").clicked() || input.key_pressed(Key::Space) {
+                        result = player.pause();
+                    }
+                } else if ui.button("Play").on_hover_text("Space").clicked() || input.key_pressed(Key::Space) {
+                    result = player.play();
+                }
+                if ui.button("Next frame").on_hover_text("Right arrow").clicked() || input.key_pressed(Key::ArrowRight) {
+                    result = player.step_frame();
+                }
+                let mut seconds = position.as_secs_f64();
+                let slider = egui::Slider::new(&mut seconds, 0.0..=duration.as_secs_f64()).show_value(false);
+                if ui.add(slider).changed() {
+                    result = player.seek(Duration::from_secs_f64(seconds));
+                }
+                ui.label(format!("{} / {}", format_duration(position), format_duration(duration)));
+                if ui.button("Close").clicked() {
+                    close = true;
+                }
+            });
+            if let Err(e) = result {
+                self.error_msg = Some(e.to_string());
+            }
+            // keeps the position updated, frames alone stop coming when paused
+            if player.is_playing() {
+                ctx.request_repaint_after(Duration::from_millis(250));
+            }
+        }
+        if close {
+            self.close_player();
+        }
+    }
+
+    fn stop_streaming(&mut self) {
+        self._streaming.take();
+        self.current_image = Arc::new(Mutex::new(Some(egui::ColorImage::new(
+            [200, 200],
+            Color32::BLACK))));
+        self.receivers.clear();
+        self.stats = None;
+        self.recording = None;
+        self.next_snapshot = None;
+        self.snapshot = None;
+        self.annotator = Annotator::default();
+        self.transmission_status = TransmissionStatus::Idle;
+    }
 }
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let events: Vec<_> = self.events.lock().unwrap().drain(..).collect();
+        for event in events {
+            self.handle_event(event);
+        }
+        self.poll_pending_source(ctx);
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("Screen-Caster");
 
@@ -122,15 +1029,24 @@ impl eframe::App for MyApp {
                 ui.add_enabled_ui(self.transmission_status == TransmissionStatus::Idle, |ui| {
                     if ui.radio(self.mode == Mode::Caster, "Caster").clicked() {
                         self.error_msg.take();
+                        self.close_player();
                         self.mode = Mode::Caster;
                     }
                 });
                 ui.add_enabled_ui(self.transmission_status == TransmissionStatus::Idle, |ui| {
                     if ui.radio(self.mode == Mode::Receiver, "Receiver").clicked() {
                         self.error_msg.take();
+                        self.close_player();
                         self.mode = Mode::Receiver;
                     }
                 });
+                ui.add_enabled_ui(self.transmission_status == TransmissionStatus::Idle, |ui| {
+                    if ui.radio(self.mode == Mode::Recordings, "Recordings").clicked() {
+                        self.error_msg.take();
+                        self.mode = Mode::Recordings;
+                        self.refresh_recordings(ctx);
+                    }
+                });
             });
 
             match &self.error_msg {
@@ -140,91 +1056,158 @@ impl eframe::App for MyApp {
                 _ => {}
             }
 
+            if let Some(capabilities) = &self.capabilities {
+                let missing = capabilities.missing();
+                if !missing.is_empty() {
+                    ui.collapsing(egui::RichText::new("Missing GStreamer plugins").color(egui::Color32::RED), |ui| {
+                        for m in missing {
+                            ui.label(m.to_string());
+                        }
+                    });
+                }
+            }
+            let can_cast = self.capabilities.as_ref().is_some_and(|c| c.can_cast() && c.sources().contains(&self.source_kind));
+            let can_receive = self.capabilities.as_ref().is_some_and(|c| c.can_receive());
+            let can_record = self.capabilities.as_ref().is_some_and(|c| c.can_record());
+
             ui.separator();
 
             match self.mode {
                 Mode::Caster => {
-                    ui.label("Select screen area:");
                     ui.horizontal(|ui| {
-                        if ui.selectable_value(&mut None, self.selected_screen_area.clone(), "Total screen").clicked(){
-                            self.selected_screen_area = None;
-                            self.slider_value1 = 0.0;
-                            self.slider_value2 = 0.0;
-                            self.slider_value3 = 0.0;
-                            self.slider_value4 = 0.0;
-                            if let Some(s) = &self._streaming {
-                                if let Streaming::Server(ss) = &s{
-                                    ss.capture_fullscreen();
+                        ui.label("Source:");
+                        let sources = self.capabilities.as_ref().map(|c| c.sources()).unwrap_or_default();
+                        ui.add_enabled_ui(self.transmission_status == TransmissionStatus::Idle, |ui| {
+                            egui::ComboBox::from_id_source("source")
+                                .selected_text(self.source_kind.to_string())
+                                .show_ui(ui, |ui| {
+                                    for kind in sources {
+                                        if ui.selectable_value(&mut self.source_kind, kind, kind.to_string()).changed()
+                                            && kind == SourceKind::Window
+                                        {
+                                            self.refresh_windows();
+                                        }
+                                    }
+                                });
+                            let hint = match self.source_kind {
+                                SourceKind::Screen | SourceKind::Window => None,
+                                SourceKind::PipeWire => Some("Node id (optional, skips the portal)"),
+                                SourceKind::Camera => Some("Device (optional)"),
+                                SourceKind::File => Some("Video file"),
+                                SourceKind::TestPattern => Some("Pattern (smpte, ball, ...)"),
+                                SourceKind::Slideshow => Some("Folder with images"),
+                            };
+                            if let Some(hint) = hint {
+                                ui.add(egui::TextEdit::singleline(&mut self.source_path).hint_text(hint));
+                            }
+                            if self.source_kind == SourceKind::Window {
+                                if ui.button("Refresh").clicked() {
+                                    self.refresh_windows();
                                 }
+                                let selected = self.windows.iter()
+                                    .find(|(id, _)| Some(*id) == self.selected_window)
+                                    .map(|(_, title)| title.clone())
+                                    .unwrap_or_else(|| "Select a window".to_string());
+                                egui::ComboBox::from_id_source("window")
+                                    .selected_text(selected)
+                                    .width(300.0)
+                                    .show_ui(ui, |ui| {
+                                        for (id, title) in &self.windows {
+                                            ui.selectable_value(&mut self.selected_window, Some(*id), title);
+                                        }
+                                    });
                             }
-                        }
-                        if ui.selectable_value(&mut true, self.selected_screen_area.is_some(), "Personalized area").clicked(){
-                            self.selected_screen_area = Some(ScreenArea {
-                                startx: 0,
-                                starty: 0,
-                                endx: 0,
-                                endy: 0,
-                            });
-                        }
-                        if self.selected_screen_area.is_some() {
-                            let mut maxs1 = self.screen_width-1;
-                            let mut maxs3 = self.screen_width-1;
-                            let mut maxs2: u32 = self.screen_height-1;
-                            let mut maxs4 = self.screen_height-1;
-
-                            if self.slider_value1 != 0.0 {
-                                maxs3 = self.screen_width-1-(self.slider_value1 as u32);
-                            };
-                            if self.slider_value3 != 0.0 {
-                                maxs1 = self.screen_width-1 -(self.slider_value3 as u32);
-                            };
-                            if self.slider_value2 != 0.0 {
-                                maxs4 = self.screen_height-1 -(self.slider_value2 as u32);
-                            };
-                            if self.slider_value4 != 0.0 {
-                                maxs2 = self.screen_height-1 -(self.slider_value4 as u32);
-                                
-                            };
-                            ui.vertical_centered_justified(|ui| {
-                                ui.horizontal(|ui| {
-                                    ui.label("Left:  ");
-                                    ui.add(egui::Slider::new(&mut self.slider_value1, 0.0..= maxs1 as f32));
-                                });
-                                ui.horizontal(|ui| {
-                                    ui.label("Right:");
-                                    ui.add(egui::Slider::new(&mut self.slider_value3, 0.0..= maxs3 as f32));
-                                });
-                            });
-                            ui.vertical_centered_justified(|ui| {
-                                ui.horizontal(|ui| {
-                                    ui.label("Top:      ");
-                                    ui.add(egui::Slider::new(&mut self.slider_value2, 0.0..= maxs2 as f32));
-                                });
-                                ui.horizontal(|ui| {
-                                    ui.label("Bottom:");
-                                    ui.add(egui::Slider::new(&mut self.slider_value4, 0.0..=maxs4 as f32));
+                        });
+                    });
+
+                    if self.source_kind == SourceKind::Screen {
+                        if self.monitors.len() > 1 {
+                            ui.horizontal(|ui| {
+                                ui.label("Monitor:");
+                                ui.add_enabled_ui(self.transmission_status == TransmissionStatus::Idle, |ui| {
+                                    let mut selected = self.selected_monitor;
+                                    egui::ComboBox::from_id_source("monitor")
+                                        .selected_text(self.monitors[selected].1.clone())
+                                        .show_ui(ui, |ui| {
+                                            for (i, (_, name)) in self.monitors.iter().enumerate() {
+                                                ui.selectable_value(&mut selected, i, name);
+                                            }
+                                        });
+                                    if selected != self.selected_monitor {
+                                        self.select_monitor(selected);
+                                    }
                                 });
                             });
-                            if let Some(Streaming::Server(ss)) = &self._streaming {
-                                let startx = self.slider_value1.round() as u32;
-                                let starty = self.slider_value2.round() as u32;
-                                let endx = self.screen_width - self.slider_value3.round() as u32;
-                                let endy = self.screen_height - self.slider_value4.round() as u32;
-
+                        }
+                        ui.label("Select screen area:");
+                        ui.horizontal(|ui| {
+                            if ui.selectable_label(self.selected_screen_area.is_none(), "Total screen").clicked() {
+                                self.selected_screen_area = None;
+                            }
+                            if ui.selectable_label(self.selected_screen_area.is_some(), "Personalized area").clicked() {
+                                self.open_area_selector();
+                            }
+                            let (width, height) = (self.screen_width, self.screen_height);
+                            if let Some(area) = &mut self.selected_screen_area {
+                                area_editor(ui, area, width, height);
+                            }
+                        });
+                        if let Some(Streaming::Server(ss)) = &self._streaming {
+                            match &self.selected_screen_area {
                                 #[cfg(any(target_os = "linux", target_os = "windows"))]
-                                ss.capture_resize(startx, starty, endx, endy);
+                                Some(area) => ss.capture_resize(area.startx, area.starty, area.endx, area.endy),
                                 #[cfg(target_os = "macos")]
-                                ss.capture_resize(
-                                    self.slider_value1.round() as u32,
-                                    self.slider_value2.round() as u32,
-                                    self.slider_value3.round() as u32, 
-                                    self.slider_value4.round() as u32,
-                                );
+                                Some(area) => ss.capture_resize(
+                                    area.startx,
+                                    area.starty,
+                                    self.screen_width - area.endx,
+                                    self.screen_height - area.endy,
+                                ),
+                                None => ss.capture_fullscreen(),
                             }
-                        };
-                        if !self.selected_screen_area.is_some() {
+                        }
+                    }
+                    if self.mask_editor.ui(ui) {
+                        self.send_masks();
+                    }
+                    #[cfg(target_os = "linux")]
+                    if self.source_kind == SourceKind::Screen {
+                        ui.horizontal(|ui| {
+                            let mut masked = None;
+                            egui::ComboBox::from_id_source("mask_window")
+                                .selected_text("Mask a window")
+                                .width(300.0)
+                                .show_ui(ui, |ui| {
+                                    for (id, title) in &self.windows {
+                                        if ui.selectable_label(false, title).clicked() {
+                                            masked = Some(*id);
+                                        }
+                                    }
+                                });
+                            if ui.button("Refresh").clicked() {
+                                self.refresh_windows();
+                            }
+                            if let Some(id) = masked {
+                                self.mask_window(id);
+                            }
+                        });
+                    }
+                    ui.horizontal(|ui| {
+                        let mut output = self.output;
+                        ui.label("Output:");
+                        egui::ComboBox::from_id_source("output_height")
+                            .selected_text(output.height.map_or("Native".to_string(), |h| format!("{}p", h)))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut output.height, None, "Native");
+                                for height in OUTPUT_HEIGHTS {
+                                    ui.selectable_value(&mut output.height, Some(height), format!("{}p", height));
+                                }
+                            });
+                        ui.add(egui::Slider::new(&mut output.framerate, MIN_FRAMERATE..=MAX_FRAMERATE).suffix(" fps"));
+                        if output != self.output {
+                            self.output = output;
                             if let Some(Streaming::Server(ss)) = &self._streaming {
-                                ss.capture_fullscreen();
+                                ss.set_output(output);
                             }
                         }
                     });
@@ -236,6 +1219,7 @@ impl eframe::App for MyApp {
                         ui.text_edit_singleline(&mut self.caster_address)
                     });
                 }
+                Mode::Recordings => self.recordings_ui(ui, ctx),
             }
 
             ui.separator();
@@ -244,145 +1228,40 @@ impl eframe::App for MyApp {
                 TransmissionStatus::Idle => {
                     match self.mode {
                         Mode::Caster => {
-                            if ui.button("Start trasmission").clicked() {
-                                if let Some(s) = &self._streaming{
-                                    match s {
-                                        Streaming::Client(_) => {
-                                            let image_clone = self.current_image.clone();
-                                            match Streaming::new_server(move |bytes| {
-                                                let image = image::load_from_memory_with_format(bytes, ImageFormat::Jpeg)
-                                                    .unwrap()
-                                                    .to_rgba8();
-                        
-                                                let size = [image.width() as usize, image.height() as usize];
-                                                let image = egui::ColorImage::from_rgba_premultiplied(size, &image);
-                        
-                                                *image_clone.lock().unwrap() = Some(image);
-                                            }) {
-                                                Ok(s) => {
-                                                    self._streaming = Some(s);
-                                                }
-                                                Err(e) => {
-                                                    self.error_msg = Some(e.to_string());
-                                                }
-                                            }
-                                        }
-                                        Streaming::Server(_) => { /* Nothing to do because it is already a streaming server */ }
-                                    }
-            
-                                }
-                                else{
-                                    let image_clone = self.current_image.clone();
-                                    match Streaming::new_server(move |bytes| {
-                                        let image = image::load_from_memory_with_format(bytes, ImageFormat::Jpeg)
-                                            .unwrap()
-                                            .to_rgba8();
-                
-                                        let size = [image.width() as usize, image.height() as usize];
-                                        let image = egui::ColorImage::from_rgba_premultiplied(size, &image);
-                
-                                        *image_clone.lock().unwrap() = Some(image);
-                                    }) {
-                                        Ok(s) => {
-                                            self._streaming = Some(s);
-                                        }
-                                        Err(e) => {
-                                            self.error_msg = Some(e.to_string());
-                                        }
-                                    }
-                                }
-                                if let Some(s) = &self._streaming{
-                                    self.pause = false;
-                                    self.blanking_screen = false;
-                                    self.error_msg.take();
-                                    match s.start(){
-                                        Ok(_) => {
-                                            self.transmission_status = TransmissionStatus::Casting;
-                                        }
-                                        Err(e) => {
-                                            self.error_msg = Some(e.to_string());
+                            ui.horizontal(|ui| {
+                                ui.label("Encoder:");
+                                let encoders = self.capabilities.as_ref().map(|c| c.encoders()).unwrap_or_default();
+                                egui::ComboBox::from_id_source("encoder")
+                                    .selected_text(self.encoder.to_string())
+                                    .show_ui(ui, |ui| {
+                                        for encoder in encoders {
+                                            ui.selectable_value(&mut self.encoder, encoder, encoder.to_string());
                                         }
-                                    }
-                                    
-                                }
+                                    });
+                                ui.checkbox(&mut self.simulcast, "Simulcast")
+                                    .on_hover_text("Send 1080p, 720p and 360p at the same time, each receiver gets one of them");
+                            });
+                            self.watermarks_ui(ui);
+                            let choosing = self.pending_source.is_some();
+                            if choosing {
+                                ui.label("Waiting for the screen to share...");
+                            }
+                            if ui.add_enabled(can_cast && !choosing, egui::Button::new("Start trasmission")).clicked() {
+                                self.choose_source(ctx);
                             }
                         }
                         Mode::Receiver => {
+                            self.recording_options_ui(ui);
                             ui.horizontal(|ui| {
-                                if ui.button("Start reception without recording").clicked() {
-                                    if is_valid_ipv4(&self.caster_address){
-                                        let image_clone = self.current_image.clone();
-                                        match Streaming::new_client(&self.caster_address, move |bytes| {
-                                            let image = image::load_from_memory_with_format(bytes, ImageFormat::Jpeg)
-                                                .unwrap()
-                                                .to_rgba8();
-                    
-                                            let size = [image.width() as usize, image.height() as usize];
-                                            let image = egui::ColorImage::from_rgba_premultiplied(size, &image);
-                    
-                                            *image_clone.lock().unwrap() = Some(image);
-                                        }, false) {
-                                            Ok(s) => {
-                                                self._streaming = Some(s);
-                                            }
-                                            Err(e) => {
-                                                self.error_msg = Some(e.to_string());
-                                            }
-                                        }
-                                        if let Some(s) = &self._streaming{
-                                            self.error_msg.take();
-                                            match s.start(){
-                                                Ok(_) => {
-                                                    self.transmission_status = TransmissionStatus::Receiving;
-                                                }
-                                                Err(e) => {
-                                                    self.error_msg = Some(e.to_string());
-                                                }
-                                            }
-                                        }
-                                    }
-                                    else{
-                                        self.error_msg = Some("Please insert a valid IP address!".to_string());
-                                    }
+                                if ui.add_enabled(can_receive, egui::Button::new("Start reception without recording")).clicked() {
+                                    self.start_receiving(false, ctx);
                                 }
-                                if ui.button("Start reception and save recording").clicked() {
-                                    if is_valid_ipv4(&self.caster_address){
-                                        let image_clone = self.current_image.clone();
-                                        match Streaming::new_client(&self.caster_address, move |bytes| {
-                                            let image = image::load_from_memory_with_format(bytes, ImageFormat::Jpeg)
-                                                .unwrap()
-                                                .to_rgba8();
-                    
-                                            let size = [image.width() as usize, image.height() as usize];
-                                            let image = egui::ColorImage::from_rgba_premultiplied(size, &image);
-                    
-                                            *image_clone.lock().unwrap() = Some(image);
-                                        },true) {
-                                            Ok(s) => {
-                                                self._streaming = Some(s);
-                                            }
-                                            Err(e) => {
-                                                self.error_msg = Some(e.to_string());
-                                            }
-                                        }
-                                        if let Some(s) = &self._streaming{
-                                            self.error_msg.take();
-                                            match s.start(){
-                                                Ok(_) => {
-                                                    self.transmission_status = TransmissionStatus::Receiving;
-                                                }
-                                                Err(e) => {
-                                                    self.error_msg = Some(e.to_string());
-                                                }
-                                            }
-                                        }
-                                    }
-                                    else{
-                                        self.error_msg = Some("Please insert a valid IP address!".to_string());
-                                    }
+                                if ui.add_enabled(can_receive && can_record, egui::Button::new("Start reception and save recording")).clicked() {
+                                    self.start_receiving(true, ctx);
                                 }
                             });
                         }
+                        Mode::Recordings => {}
                     }
                 }
                 TransmissionStatus::Casting => {
@@ -393,13 +1272,40 @@ impl eframe::App for MyApp {
                     else{
                         ui.colored_label(egui::Color32::LIGHT_RED, "Pause...");
                     }
+                    ui.label(format!("Receivers: {}", self.receivers.len()));
+                    if self.simulcast {
+                        let mut changed = None;
+                        for (ip, layer) in &self.receivers {
+                            ui.horizontal(|ui| {
+                                ui.label(ip);
+                                let mut selected = *layer;
+                                egui::ComboBox::from_id_source(("layer", ip))
+                                    .selected_text(SimulcastLayer::DEFAULTS[*layer].to_string())
+                                    .show_ui(ui, |ui| {
+                                        for (i, l) in SimulcastLayer::DEFAULTS.iter().enumerate() {
+                                            ui.selectable_value(&mut selected, i, l.to_string());
+                                        }
+                                    });
+                                if selected != *layer {
+                                    changed = Some((ip.clone(), selected));
+                                }
+                            });
+                        }
+                        if let (Some((ip, layer)), Some(Streaming::Server(s))) = (changed, &self._streaming) {
+                            s.set_receiver_layer(&ip, layer);
+                        }
+                    }
+                    else {
+                        for (ip, _) in &self.receivers {
+                            ui.label(ip);
+                        }
+                    }
+                    if let Some(stats) = &self.stats {
+                        ui.label(format!("{:.1} fps", stats.fps));
+                    }
                     ui.horizontal(|ui| {
                         if ui.button("Stop transmission").on_hover_text("Ctrl + T").clicked() || input.key_pressed(Key::T) && input.modifiers.ctrl{
-                            self._streaming.take();
-                            self.current_image = Arc::new(Mutex::new(Some(egui::ColorImage::new(
-                                [200, 200],
-                                Color32::BLACK))));
-                            self.transmission_status = TransmissionStatus::Idle;
+                            self.stop_streaming();
                         }
 
                         if ui.add_enabled(!self.pause, egui::Button::new("Pause")).on_hover_text("Ctrl + P").clicked() || input.key_pressed(Key::P) && input.modifiers.ctrl{
@@ -430,36 +1336,38 @@ impl eframe::App for MyApp {
                         }
                         if ui.selectable_value(&mut self.blanking_screen.clone(), true, "Blanking screen").on_hover_text("Ctrl + B").clicked() || input.key_pressed(Key::B) && input.modifiers.ctrl {
                             self.blanking_screen = !self.blanking_screen;
-                            if let Some(Streaming::Server(s)) = &self._streaming {
-                                if self.blanking_screen {
-                                    s.blank_screen();
-                                } else {
-                                    s.restore_screen();
-                                }
+                            if self.blanking_screen {
+                                self.blank_screen();
+                            } else if let Some(Streaming::Server(s)) = &self._streaming {
+                                s.restore_screen();
                             }
                         }
                     });
-
+                    self.blank_content_ui(ui);
+                    if self.annotator.toolbar(ui) {
+                        self.send_annotations();
+                    }
+                    self.snapshot_ui(ui, ctx);
+                    self.recording_ui(ui);
                 }
                 TransmissionStatus::Receiving => {
-                    ui.label(format!("Receiving..."));
+                    match &self._streaming {
+                        Some(Streaming::Client(s)) if s.is_stalled() => {
+                            ui.colored_label(egui::Color32::LIGHT_RED, "Stream stalled...");
+                            ctx.request_repaint_after(std::time::Duration::from_millis(500));
+                        }
+                        _ => {
+                            ui.label(format!("Receiving..."));
+                        }
+                    }
+                    if let Some(stats) = &self.stats {
+                        ui.label(format!("{:.1} fps", stats.fps));
+                    }
+                    self.snapshot_ui(ui, ctx);
+                    self.recording_ui(ui);
                     if ui.button("Stop reception").clicked() {
-                        self._streaming.take();
                         self.caster_address = String::default();
-                        self.current_image = Arc::new(Mutex::new(Some(egui::ColorImage::new(
-                            [200, 200],
-                            Color32::BLACK))));
-                        self.transmission_status = TransmissionStatus::Idle;
-                    }
-                    if let Some(Streaming::Client(s)) = &self._streaming {
-                        if !s.is_connected() {
-                            self._streaming.take();
-                            self.caster_address = String::default();
-                            self.current_image = Arc::new(Mutex::new(Some(egui::ColorImage::new(
-                                [200, 200],
-                                Color32::BLACK))));
-                            self.transmission_status = TransmissionStatus::Idle;
-                        }
+                        self.stop_streaming();
                     }
                 }
             }
@@ -471,8 +1379,35 @@ impl eframe::App for MyApp {
             drop(data);
 
             if let Some(texture) = &self.texture {
-                ui.add(egui::Image::from_texture(texture).shrink_to_fit());
+                if self.transmission_status == TransmissionStatus::Casting {
+                    let response = ui.add(egui::Image::from_texture(texture).shrink_to_fit().sense(egui::Sense::click_and_drag()));
+                    if self.mask_editor.is_drawing() {
+                        if self.mask_editor.interact(&response) {
+                            self.send_masks();
+                        }
+                    }
+                    else if self.annotator.interact(&response) {
+                        self.send_annotations();
+                    }
+                    let painter = ui.painter_at(response.rect);
+                    self.mask_editor.paint(&painter, response.rect);
+                    self.annotator.paint(&painter, response.rect);
+                }
+                else {
+                    ui.add(egui::Image::from_texture(texture).shrink_to_fit());
+                }
             }
         });
+
+        if let Some(selector) = &mut self.area_selector {
+            match selector.show(ctx) {
+                Selection::Pending => {}
+                Selection::Done(area) => {
+                    self.selected_screen_area = Some(area);
+                    self.area_selector = None;
+                }
+                Selection::Cancelled => self.area_selector = None,
+            }
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,217 @@
+use eframe::egui::{self, Align2, Color32, FontId, Pos2, Rect, Response, Stroke};
+
+use crate::streaming::annotation::{
+    arrow_head, Annotation, Point, Shape, ARROW_HEAD_SCALE, REFERENCE_HEIGHT, TEXT_SCALE,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Tool {
+    Pen,
+    Arrow,
+    Rectangle,
+    Text,
+}
+
+impl Tool {
+    const ALL: [Tool; 4] = [Tool::Pen, Tool::Arrow, Tool::Rectangle, Tool::Text];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Tool::Pen => "Pen",
+            Tool::Arrow => "Arrow",
+            Tool::Rectangle => "Rectangle",
+            Tool::Text => "Text",
+        }
+    }
+}
+
+/// Annotations the caster draws on the preview, sent to `StreamingServer::set_annotations`
+pub struct Annotator {
+    annotations: Vec<Annotation>,
+    /// The annotation being dragged
+    current: Option<Annotation>,
+    /// `None` when not annotating, the preview ignores the pointer
+    tool: Option<Tool>,
+    color: Color32,
+    width: f32,
+    text: String,
+}
+
+impl Default for Annotator {
+    fn default() -> Self {
+        Self {
+            annotations: Vec::new(),
+            current: None,
+            tool: None,
+            color: Color32::RED,
+            width: 4.0,
+            text: String::new(),
+        }
+    }
+}
+
+impl Annotator {
+    /// The annotations to send, including the one being drawn
+    pub fn annotations(&self) -> Vec<Annotation> {
+        self.annotations
+            .iter()
+            .chain(&self.current)
+            .cloned()
+            .collect()
+    }
+
+    /// Tools, color and width. Returns true if the annotations changed
+    pub fn toolbar(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Annotate:");
+            ui.selectable_value(&mut self.tool, None, "Off");
+            for tool in Tool::ALL {
+                ui.selectable_value(&mut self.tool, Some(tool), tool.name());
+            }
+            ui.color_edit_button_srgba(&mut self.color);
+            ui.add(
+                egui::DragValue::new(&mut self.width)
+                    .clamp_range(1.0..=20.0)
+                    .speed(0.1)
+                    .suffix(" px"),
+            );
+            if self.tool == Some(Tool::Text) {
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.text).hint_text("Text, click to place it"),
+                );
+            }
+            if ui
+                .add_enabled(!self.annotations.is_empty(), egui::Button::new("Undo"))
+                .clicked()
+            {
+                self.annotations.pop();
+                changed = true;
+            }
+            if ui
+                .add_enabled(!self.annotations.is_empty(), egui::Button::new("Clear"))
+                .clicked()
+            {
+                self.annotations.clear();
+                changed = true;
+            }
+        });
+        changed
+    }
+
+    /// Draws with the pointer on the preview, `response` must sense clicks and drags.
+    /// Returns true if the annotations changed
+    pub fn interact(&mut self, response: &Response) -> bool {
+        let Some(tool) = self.tool else {
+            return false;
+        };
+        let rect = response.rect;
+        let Some(point) = response
+            .interact_pointer_pos()
+            .map(|pos| to_point(pos, rect))
+        else {
+            return false;
+        };
+
+        if tool == Tool::Text {
+            if response.clicked() && !self.text.trim().is_empty() {
+                let text = std::mem::take(&mut self.text);
+                self.annotations
+                    .push(self.annotation(Shape::Text { at: point, text }));
+                return true;
+            }
+            return false;
+        }
+
+        if response.drag_started() {
+            let shape = match tool {
+                Tool::Pen => Shape::Pen(vec![point]),
+                Tool::Arrow => Shape::Arrow {
+                    from: point,
+                    to: point,
+                },
+                _ => Shape::Rectangle {
+                    from: point,
+                    to: point,
+                },
+            };
+            self.current = Some(self.annotation(shape));
+            return true;
+        }
+        if response.dragged() {
+            if let Some(current) = &mut self.current {
+                match &mut current.shape {
+                    Shape::Pen(points) => points.push(point),
+                    Shape::Arrow { to, .. } | Shape::Rectangle { to, .. } => *to = point,
+                    Shape::Text { .. } => {}
+                }
+                return true;
+            }
+        }
+        if response.drag_stopped() {
+            if let Some(current) = self.current.take() {
+                self.annotations.push(current);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Paints the annotation being drawn over the preview shown in `rect`, the preview
+    /// already shows the others
+    pub fn paint(&self, painter: &egui::Painter, rect: Rect) {
+        let scale = rect.height() / REFERENCE_HEIGHT;
+        if let Some(annotation) = &self.current {
+            let [r, g, b, a] = annotation.color;
+            let color = Color32::from_rgba_unmultiplied(r, g, b, a);
+            let width = annotation.width * scale;
+            let stroke = Stroke::new(width, color);
+            match &annotation.shape {
+                Shape::Pen(points) => {
+                    let points = points.iter().map(|p| to_pos(*p, rect)).collect();
+                    painter.add(egui::Shape::line(points, stroke));
+                }
+                Shape::Arrow { from, to } => {
+                    let (from, to) = (to_pos(*from, rect), to_pos(*to, rect));
+                    painter.line_segment([from, to], stroke);
+                    for (x, y) in
+                        arrow_head((from.x, from.y), (to.x, to.y), width * ARROW_HEAD_SCALE)
+                    {
+                        painter.line_segment([to, Pos2::new(x, y)], stroke);
+                    }
+                }
+                Shape::Rectangle { from, to } => {
+                    let rect = Rect::from_two_pos(to_pos(*from, rect), to_pos(*to, rect));
+                    painter.rect_stroke(rect, 0.0, stroke);
+                }
+                Shape::Text { at, text } => {
+                    let font = FontId::proportional(width * TEXT_SCALE);
+                    painter.text(to_pos(*at, rect), Align2::LEFT_TOP, text, font, color);
+                }
+            }
+        }
+    }
+
+    fn annotation(&self, shape: Shape) -> Annotation {
+        Annotation {
+            shape,
+            color: self.color.to_srgba_unmultiplied(),
+            width: self.width,
+        }
+    }
+}
+
+/// `pos` on the preview shown in `rect`, relative to the frame
+pub(super) fn to_point(pos: Pos2, rect: Rect) -> Point {
+    (
+        ((pos.x - rect.min.x) / rect.width()).clamp(0.0, 1.0),
+        ((pos.y - rect.min.y) / rect.height()).clamp(0.0, 1.0),
+    )
+}
+
+pub(super) fn to_pos((x, y): Point, rect: Rect) -> Pos2 {
+    Pos2::new(
+        rect.min.x + x * rect.width(),
+        rect.min.y + y * rect.height(),
+    )
+}
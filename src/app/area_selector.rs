@@ -0,0 +1,261 @@
+use eframe::egui::{self, Align2, Color32, FontId, Key, Pos2, Rect, Sense, Stroke};
+
+use super::ScreenArea;
+use crate::streaming::source::Monitor;
+
+/// Distance in pixels below which an edge snaps to the edge of a window
+const SNAP_DISTANCE: u32 = 12;
+/// Smallest width and height of an area
+const MIN_SIZE: u32 = 16;
+
+pub enum Selection {
+    Pending,
+    Done(ScreenArea),
+    Cancelled,
+}
+
+/// Fullscreen translucent overlay on top of the captured monitor, where the caster drags
+/// the area to capture. Edges snap to the open windows, a click selects the window below it
+pub struct AreaSelector {
+    monitor: Monitor,
+    /// Open windows, relative to the monitor
+    windows: Vec<ScreenArea>,
+    area: Option<ScreenArea>,
+    drag_origin: Option<(u32, u32)>,
+}
+
+impl AreaSelector {
+    /// `windows` are (x, y, width, height) of the open windows on the whole desktop
+    pub fn new(
+        monitor: Monitor,
+        area: Option<ScreenArea>,
+        windows: &[(i32, i32, u32, u32)],
+    ) -> Self {
+        let clamp = |v: i32, offset: i32, max: u32| (v - offset).clamp(0, max as i32) as u32;
+        let windows = windows
+            .iter()
+            .map(|&(x, y, width, height)| ScreenArea {
+                startx: clamp(x, monitor.x, monitor.width),
+                starty: clamp(y, monitor.y, monitor.height),
+                endx: clamp(x + width as i32, monitor.x, monitor.width),
+                endy: clamp(y + height as i32, monitor.y, monitor.height),
+            })
+            .filter(|w| w.width() > 0 && w.height() > 0)
+            .collect();
+
+        Self {
+            monitor,
+            windows,
+            area,
+            drag_origin: None,
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) -> Selection {
+        let ppp = ctx.pixels_per_point();
+        let builder = egui::ViewportBuilder::default()
+            .with_title("Select the area to capture")
+            .with_position([self.monitor.x as f32 / ppp, self.monitor.y as f32 / ppp])
+            .with_inner_size([
+                self.monitor.width as f32 / ppp,
+                self.monitor.height as f32 / ppp,
+            ])
+            .with_decorations(false)
+            .with_transparent(true)
+            .with_always_on_top()
+            .with_fullscreen(true);
+
+        ctx.show_viewport_immediate(
+            egui::ViewportId::from_hash_of("area_selector"),
+            builder,
+            |ctx, _| self.ui(ctx),
+        )
+    }
+
+    fn ui(&mut self, ctx: &egui::Context) -> Selection {
+        if ctx.input(|i| i.key_pressed(Key::Escape) || i.viewport().close_requested()) {
+            return Selection::Cancelled;
+        }
+        let valid = self
+            .area
+            .filter(|a| a.width() >= MIN_SIZE && a.height() >= MIN_SIZE);
+        if let Some(area) = valid {
+            if ctx.input(|i| i.key_pressed(Key::Enter)) {
+                return Selection::Done(area);
+            }
+        }
+
+        let ppp = ctx.pixels_per_point();
+        let mut selection = Selection::Pending;
+
+        egui::CentralPanel::default()
+            .frame(egui::Frame::none().fill(Color32::from_black_alpha(100)))
+            .show(ctx, |ui| {
+                let response =
+                    ui.interact(ui.max_rect(), ui.id().with("drag"), Sense::click_and_drag());
+                if let Some(pos) = response.interact_pointer_pos() {
+                    let point = self.to_pixels(pos, ppp);
+                    if response.drag_started() {
+                        self.drag_origin = Some(point);
+                    }
+                    if let (true, Some(origin)) = (response.dragged(), self.drag_origin) {
+                        self.area = Some(self.snap(ScreenArea::from_corners(origin, point)));
+                    }
+                    if response.clicked() {
+                        if let Some(window) = self.window_at(point) {
+                            self.area = Some(window);
+                        }
+                    }
+                }
+                if response.drag_stopped() {
+                    self.drag_origin = None;
+                }
+
+                let painter = ui.painter();
+                match self.area {
+                    Some(area) => {
+                        let rect = Rect::from_min_max(
+                            Pos2::new(area.startx as f32, area.starty as f32) / ppp,
+                            Pos2::new(area.endx as f32, area.endy as f32) / ppp,
+                        );
+                        painter.rect(
+                            rect,
+                            0.0,
+                            Color32::from_white_alpha(30),
+                            Stroke::new(2.0, Color32::LIGHT_BLUE),
+                        );
+                        painter.text(
+                            rect.left_top() - egui::vec2(0.0, 4.0),
+                            Align2::LEFT_BOTTOM,
+                            format!("{}x{}", area.width(), area.height()),
+                            FontId::proportional(14.0),
+                            Color32::WHITE,
+                        );
+                    }
+                    None => {
+                        painter.text(
+                            ui.max_rect().center(),
+                            Align2::CENTER_CENTER,
+                            "Drag to select the area to capture, or click on a window",
+                            FontId::proportional(20.0),
+                            Color32::WHITE,
+                        );
+                    }
+                }
+            });
+
+        egui::Area::new(egui::Id::new("area_selector_controls"))
+            .anchor(Align2::CENTER_TOP, [0.0, 16.0])
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        if let Some(area) = &mut self.area {
+                            area_editor(ui, area, self.monitor.width, self.monitor.height);
+                        }
+                        if ui
+                            .add_enabled(valid.is_some(), egui::Button::new("Confirm"))
+                            .clicked()
+                        {
+                            if let Some(area) = self.area {
+                                selection = Selection::Done(area);
+                            }
+                        }
+                        if ui.button("Cancel").clicked() {
+                            selection = Selection::Cancelled;
+                        }
+                    });
+                });
+            });
+
+        selection
+    }
+
+    fn to_pixels(&self, pos: Pos2, ppp: f32) -> (u32, u32) {
+        (
+            ((pos.x * ppp).round().max(0.0) as u32).min(self.monitor.width),
+            ((pos.y * ppp).round().max(0.0) as u32).min(self.monitor.height),
+        )
+    }
+
+    /// Moves every edge close enough to the edge of a window (or of the monitor) onto it
+    fn snap(&self, area: ScreenArea) -> ScreenArea {
+        let xs: Vec<u32> = self
+            .windows
+            .iter()
+            .flat_map(|w| [w.startx, w.endx])
+            .chain([0, self.monitor.width])
+            .collect();
+        let ys: Vec<u32> = self
+            .windows
+            .iter()
+            .flat_map(|w| [w.starty, w.endy])
+            .chain([0, self.monitor.height])
+            .collect();
+
+        ScreenArea {
+            startx: snap_to(area.startx, &xs),
+            starty: snap_to(area.starty, &ys),
+            endx: snap_to(area.endx, &xs),
+            endy: snap_to(area.endy, &ys),
+        }
+    }
+
+    /// The smallest window containing the point, the topmost one is not known
+    fn window_at(&self, (x, y): (u32, u32)) -> Option<ScreenArea> {
+        self.windows
+            .iter()
+            .filter(|w| (w.startx..w.endx).contains(&x) && (w.starty..w.endy).contains(&y))
+            .min_by_key(|w| w.width() * w.height())
+            .copied()
+    }
+}
+
+fn snap_to(value: u32, edges: &[u32]) -> u32 {
+    edges
+        .iter()
+        .copied()
+        .filter(|e| e.abs_diff(value) <= SNAP_DISTANCE)
+        .min_by_key(|e| e.abs_diff(value))
+        .unwrap_or(value)
+}
+
+/// Numeric fine-tuning of an area inside a `max_width`x`max_height` screen,
+/// returns true if it changed
+pub fn area_editor(
+    ui: &mut egui::Ui,
+    area: &mut ScreenArea,
+    max_width: u32,
+    max_height: u32,
+) -> bool {
+    let mut x = area.startx;
+    let mut y = area.starty;
+    let mut width = area.width();
+    let mut height = area.height();
+
+    ui.label("X:");
+    let mut changed = ui
+        .add(egui::DragValue::new(&mut x).clamp_range(0..=max_width.saturating_sub(MIN_SIZE)))
+        .changed();
+    ui.label("Y:");
+    changed |= ui
+        .add(egui::DragValue::new(&mut y).clamp_range(0..=max_height.saturating_sub(MIN_SIZE)))
+        .changed();
+    ui.label("Width:");
+    changed |= ui
+        .add(egui::DragValue::new(&mut width).clamp_range(MIN_SIZE.min(max_width)..=max_width))
+        .changed();
+    ui.label("Height:");
+    changed |= ui
+        .add(egui::DragValue::new(&mut height).clamp_range(MIN_SIZE.min(max_height)..=max_height))
+        .changed();
+
+    if changed {
+        *area = ScreenArea {
+            startx: x,
+            starty: y,
+            endx: x + width.min(max_width - x),
+            endy: y + height.min(max_height - y),
+        };
+    }
+    changed
+}
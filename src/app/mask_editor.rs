@@ -0,0 +1,139 @@
+use eframe::egui::{self, Color32, Rect, Response, Stroke};
+
+use super::annotator::{to_point, to_pos};
+use crate::streaming::privacy::{MaskStyle, PrivacyMask};
+
+/// Privacy masks of the cast, drawn by dragging on the preview
+#[derive(Default)]
+pub struct MaskEditor {
+    masks: Vec<PrivacyMask>,
+    /// The mask being dragged
+    current: Option<PrivacyMask>,
+    /// Dragging on the preview draws a mask instead of an annotation
+    drawing: bool,
+    style: MaskStyle,
+}
+
+impl MaskEditor {
+    /// The masks to send, including the one being drawn
+    pub fn masks(&self) -> Vec<PrivacyMask> {
+        self.masks.iter().chain(&self.current).copied().collect()
+    }
+
+    pub fn is_drawing(&self) -> bool {
+        self.drawing
+    }
+
+    /// Adds a mask over a region given relative to the frame, e.g. a window
+    #[cfg(target_os = "linux")]
+    pub fn add(&mut self, from: (f32, f32), to: (f32, f32)) {
+        self.masks.push(PrivacyMask {
+            from,
+            to,
+            style: self.style,
+        });
+    }
+
+    /// List of the masks with their style. Returns true if the masks changed
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Privacy masks:");
+            ui.toggle_value(&mut self.drawing, "Draw on the preview");
+            egui::ComboBox::from_id_source("mask_style")
+                .selected_text(self.style.to_string())
+                .show_ui(ui, |ui| {
+                    for style in MaskStyle::ALL {
+                        ui.selectable_value(&mut self.style, style, style.to_string());
+                    }
+                });
+            if ui
+                .add_enabled(!self.masks.is_empty(), egui::Button::new("Clear"))
+                .clicked()
+            {
+                self.masks.clear();
+                changed = true;
+            }
+        });
+
+        let mut remove = None;
+        for (i, mask) in self.masks.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "Mask {}: {:.0}%, {:.0}% to {:.0}%, {:.0}%",
+                    i + 1,
+                    mask.from.0 * 100.0,
+                    mask.from.1 * 100.0,
+                    mask.to.0 * 100.0,
+                    mask.to.1 * 100.0
+                ));
+                egui::ComboBox::from_id_source(("mask", i))
+                    .selected_text(mask.style.to_string())
+                    .show_ui(ui, |ui| {
+                        for style in MaskStyle::ALL {
+                            changed |= ui
+                                .selectable_value(&mut mask.style, style, style.to_string())
+                                .changed();
+                        }
+                    });
+                if ui.button("Remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            self.masks.remove(i);
+            changed = true;
+        }
+        changed
+    }
+
+    /// Drags a new mask on the preview, `response` must sense drags.
+    /// Returns true if the masks changed
+    pub fn interact(&mut self, response: &Response) -> bool {
+        if !self.drawing {
+            return false;
+        }
+        let rect = response.rect;
+        let Some(point) = response
+            .interact_pointer_pos()
+            .map(|pos| to_point(pos, rect))
+        else {
+            return false;
+        };
+
+        if response.drag_started() {
+            self.current = Some(PrivacyMask {
+                from: point,
+                to: point,
+                style: self.style,
+            });
+            return true;
+        }
+        if response.dragged() {
+            if let Some(current) = &mut self.current {
+                current.to = point;
+                return true;
+            }
+        }
+        if response.drag_stopped() {
+            if let Some(current) = self.current.take() {
+                self.masks.push(current);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Outlines the masks over the preview shown in `rect`, the preview already hides them
+    pub fn paint(&self, painter: &egui::Painter, rect: Rect) {
+        let stroke = Stroke::new(1.0, Color32::YELLOW);
+        for mask in self.masks.iter().chain(&self.current) {
+            painter.rect_stroke(
+                Rect::from_two_pos(to_pos(mask.from, rect), to_pos(mask.to, rect)),
+                0.0,
+                stroke,
+            );
+        }
+    }
+}
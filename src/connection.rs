@@ -1,2 +1,82 @@
+use std::time::Duration;
+
 pub mod client;
 pub mod server;
+
+/// How often the caster pings every receiver.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// A peer that has not been heard of for this long is considered gone.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Internal signals of the websocket nodes.
+enum Signal {
+    Heartbeat,
+}
+
+/// Messages exchanged between caster and receivers on the websocket control channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMessage {
+    /// The caster is stopping, no more media will be sent.
+    StreamEnding,
+    Ping,
+    Pong,
+}
+
+impl ControlMessage {
+    pub fn to_bytes(self) -> [u8; 1] {
+        match self {
+            ControlMessage::StreamEnding => [0],
+            ControlMessage::Ping => [1],
+            ControlMessage::Pong => [2],
+        }
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        match data {
+            [0] => Some(ControlMessage::StreamEnding),
+            [1] => Some(ControlMessage::Ping),
+            [2] => Some(ControlMessage::Pong),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [ControlMessage; 3] = [
+        ControlMessage::StreamEnding,
+        ControlMessage::Ping,
+        ControlMessage::Pong,
+    ];
+
+    #[test]
+    fn messages_survive_the_round_trip() {
+        for message in ALL {
+            assert_eq!(
+                ControlMessage::from_bytes(&message.to_bytes()),
+                Some(message)
+            );
+        }
+    }
+
+    #[test]
+    fn messages_have_distinct_encodings() {
+        for (i, a) in ALL.iter().enumerate() {
+            for b in &ALL[i + 1..] {
+                assert_ne!(a.to_bytes(), b.to_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_bytes_are_rejected() {
+        assert_eq!(ControlMessage::from_bytes(&[]), None);
+        assert_eq!(ControlMessage::from_bytes(&[3]), None);
+        assert_eq!(ControlMessage::from_bytes(&[255]), None);
+        // a valid message followed by garbage is not a valid message
+        assert_eq!(ControlMessage::from_bytes(&[1, 0]), None);
+        assert_eq!(ControlMessage::from_bytes(b"ping"), None);
+    }
+}
@@ -1,38 +1,81 @@
 use message_io::network::{NetEvent, Transport};
-use message_io::node::{self, NodeHandler};
+use message_io::node::{self, NodeEvent, NodeHandler};
 use std::sync::mpsc::channel;
+use std::time::Instant;
 use std::{io, thread};
 
+use super::{ControlMessage, Signal, HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT};
+
 pub struct ConnectionClient {
-    ws_handler: NodeHandler<()>,
+    ws_handler: NodeHandler<Signal>,
 }
 
 impl ConnectionClient {
     pub fn new<T: AsRef<str>>(
         ip: T,
+        mut on_message: impl FnMut(ControlMessage) -> () + Send + 'static,
         mut on_disconnect: impl FnMut() -> () + Send + 'static,
     ) -> io::Result<Self> {
-        let (ws_handler, listener) = node::split::<()>();
+        let (ws_handler, listener) = node::split::<Signal>();
 
-        ws_handler
+        let (server, _) = ws_handler
             .network()
             .connect(Transport::Ws, format!("{}:9000", ip.as_ref()))?;
 
         let (tx, rx) = channel();
+        let handler = ws_handler.clone();
 
         thread::spawn(move || {
-            listener.for_each(move |event| match event.network() {
-                NetEvent::Connected(_, success) => {
-                    tx.send(success).unwrap();
-                    if success {
-                        println!("Connected");
+            let mut last_ping = Instant::now();
+            let mut alive = true;
+            listener.for_each(move |event| match event {
+                NodeEvent::Network(net_event) => match net_event {
+                    NetEvent::Connected(_, success) => {
+                        tx.send(success).unwrap();
+                        if success {
+                            println!("Connected");
+                            last_ping = Instant::now();
+                            handler
+                                .signals()
+                                .send_with_timer(Signal::Heartbeat, HEARTBEAT_INTERVAL);
+                        } else {
+                            println!("Failed to connect");
+                        }
+                    }
+                    NetEvent::Accepted(..) => unreachable!(),
+                    NetEvent::Message(endpoint, data) => match ControlMessage::from_bytes(data) {
+                        Some(ControlMessage::Ping) => {
+                            last_ping = Instant::now();
+                            handler
+                                .network()
+                                .send(endpoint, &ControlMessage::Pong.to_bytes());
+                        }
+                        Some(message) => on_message(message),
+                        None => println!("Unknown message"),
+                    },
+                    NetEvent::Disconnected(_) => {
+                        if alive {
+                            alive = false;
+                            on_disconnect();
+                        }
+                    }
+                },
+                NodeEvent::Signal(Signal::Heartbeat) => {
+                    if !alive {
+                        return;
+                    }
+                    if last_ping.elapsed() > HEARTBEAT_TIMEOUT {
+                        // the caster stopped answering but the socket was never closed
+                        println!("Connection timed out");
+                        alive = false;
+                        handler.network().remove(server.resource_id());
+                        on_disconnect();
                     } else {
-                        println!("Failed to connect");
+                        handler
+                            .signals()
+                            .send_with_timer(Signal::Heartbeat, HEARTBEAT_INTERVAL);
                     }
                 }
-                NetEvent::Accepted(..) => unreachable!(),
-                NetEvent::Message(..) => println!("Message"),
-                NetEvent::Disconnected(_) => on_disconnect(),
             });
         });
 
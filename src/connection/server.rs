@@ -1,9 +1,16 @@
-use message_io::network::{NetEvent, Transport};
-use message_io::node::{self, NodeHandler};
+use message_io::network::{Endpoint, NetEvent, Transport};
+use message_io::node::{self, NodeEvent, NodeHandler};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use std::{io, thread};
 
+use super::{ControlMessage, Signal, HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT};
+
 pub struct ConnectionServer {
-    ws_handler: NodeHandler<()>,
+    ws_handler: NodeHandler<Signal>,
+    /// Connected receivers with the last time they answered
+    endpoints: Arc<Mutex<HashMap<Endpoint, Instant>>>,
 }
 
 impl ConnectionServer {
@@ -11,26 +18,79 @@ impl ConnectionServer {
         mut on_connect: impl FnMut(&str) -> () + Send + 'static,
         mut on_disconnect: impl FnMut(&str) -> () + Send + 'static,
     ) -> io::Result<Self> {
-        let (ws_handler, listener) = node::split::<()>();
+        let (ws_handler, listener) = node::split::<Signal>();
 
         ws_handler.network().listen(Transport::Ws, "0.0.0.0:9000")?;
+        ws_handler.signals().send(Signal::Heartbeat);
+
+        let endpoints = Arc::new(Mutex::new(HashMap::new()));
+        let endpoints_clone = endpoints.clone();
+        let handler = ws_handler.clone();
 
         thread::spawn(move || {
-            listener.for_each(move |event| match event.network() {
-                NetEvent::Connected(..) => unreachable!(),
-                NetEvent::Accepted(endpoint, _) => {
-                    let ip = endpoint.addr().ip().to_string();
-                    on_connect(&ip);
-                }
-                NetEvent::Message(..) => println!("Message"),
-                NetEvent::Disconnected(endpoint) => {
-                    let ip = endpoint.addr().ip().to_string();
-                    on_disconnect(&ip);
+            listener.for_each(move |event| match event {
+                NodeEvent::Network(net_event) => match net_event {
+                    NetEvent::Connected(..) => unreachable!(),
+                    NetEvent::Accepted(endpoint, _) => {
+                        endpoints_clone
+                            .lock()
+                            .unwrap()
+                            .insert(endpoint, Instant::now());
+                        let ip = endpoint.addr().ip().to_string();
+                        on_connect(&ip);
+                    }
+                    NetEvent::Message(endpoint, data) => match ControlMessage::from_bytes(data) {
+                        Some(ControlMessage::Pong) => {
+                            if let Some(last_seen) =
+                                endpoints_clone.lock().unwrap().get_mut(&endpoint)
+                            {
+                                *last_seen = Instant::now();
+                            }
+                        }
+                        _ => println!("Unexpected message"),
+                    },
+                    NetEvent::Disconnected(endpoint) => {
+                        if endpoints_clone.lock().unwrap().remove(&endpoint).is_some() {
+                            let ip = endpoint.addr().ip().to_string();
+                            on_disconnect(&ip);
+                        }
+                    }
+                },
+                NodeEvent::Signal(Signal::Heartbeat) => {
+                    let mut endpoints = endpoints_clone.lock().unwrap();
+                    let ping = ControlMessage::Ping.to_bytes();
+                    endpoints.retain(|endpoint, last_seen| {
+                        if last_seen.elapsed() > HEARTBEAT_TIMEOUT {
+                            // half-open connection, the receiver is gone
+                            handler.network().remove(endpoint.resource_id());
+                            let ip = endpoint.addr().ip().to_string();
+                            on_disconnect(&ip);
+                            false
+                        } else {
+                            handler.network().send(*endpoint, &ping);
+                            true
+                        }
+                    });
+                    handler
+                        .signals()
+                        .send_with_timer(Signal::Heartbeat, HEARTBEAT_INTERVAL);
                 }
             });
         });
 
-        Ok(Self { ws_handler })
+        Ok(Self {
+            ws_handler,
+            endpoints,
+        })
+    }
+
+    /// Tells every connected receiver that the stream is about to stop,
+    /// so they can finalize their pipelines before the sockets are closed.
+    pub fn notify_stream_ending(&self) {
+        let message = ControlMessage::StreamEnding.to_bytes();
+        for endpoint in self.endpoints.lock().unwrap().keys() {
+            self.ws_handler.network().send(*endpoint, &message);
+        }
     }
 }
 
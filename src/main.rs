@@ -1,6 +1,33 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
 use rust_streamer::app::MyApp;
+use rust_streamer::streaming::recording;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Repairs the MP4 recordings left unplayable by a crash
+    Recover {
+        /// Folder of the recordings
+        #[arg(default_value = ".")]
+        directory: PathBuf,
+    },
+}
 
 fn main() {
+    let cli = Cli::parse();
+    if let Some(Command::Recover { directory }) = cli.command {
+        recover(&directory);
+        return;
+    }
+
     let options = Default::default();
     eframe::run_native(
         "Image Viewer",
@@ -14,3 +41,22 @@ fn main() {
     .unwrap();
     println!("Finished");
 }
+
+fn recover(directory: &Path) {
+    let recordings = match recording::interrupted_recordings(directory) {
+        Ok(recordings) => recordings,
+        Err(e) => {
+            eprintln!("Can't read {}: {}", directory.display(), e);
+            return;
+        }
+    };
+    if recordings.is_empty() {
+        println!("Nothing to recover in {}", directory.display());
+    }
+    for path in recordings {
+        match recording::recover(&path) {
+            Ok(()) => println!("Recovered {}", path.display()),
+            Err(e) => eprintln!("Can't recover {}: {}", path.display(), e),
+        }
+    }
+}
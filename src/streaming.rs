@@ -1,5 +1,39 @@
+use std::path::{Path, PathBuf};
+
+use gstreamer::{self as gst, glib};
+
+pub mod annotation;
+pub mod blank;
+pub mod bus;
+pub mod capabilities;
 pub mod client;
+pub mod event;
+pub mod frame;
+pub mod pipeline;
+pub mod player;
+#[cfg(target_os = "linux")]
+pub mod portal;
+pub mod privacy;
+pub mod recording;
 pub mod server;
+pub mod simulcast;
+pub mod snapshot;
+pub mod source;
+pub mod watermark;
+#[cfg(target_os = "linux")]
+pub mod window;
+
+use capabilities::Capabilities;
+use event::Subscription;
+use pipeline::{ClientConfig, ServerConfig};
+use recording::RecordingOptions;
+use snapshot::{SnapshotError, SnapshotFormat};
+
+/// Checks which of the GStreamer elements used by the pipelines are installed
+pub fn capabilities() -> Result<Capabilities, glib::Error> {
+    gst::init()?;
+    Ok(Capabilities::probe())
+}
 
 pub enum Streaming {
     Client(client::StreamingClient),
@@ -9,16 +43,13 @@ pub enum Streaming {
 impl Streaming {
     pub fn new_client<T: AsRef<str>>(
         ip: T,
-        image_parser: impl FnMut(&[u8]) + Send + 'static,
-        save_stream: bool,
+        config: ClientConfig,
     ) -> Result<Self, client::StreamingClientError> {
-        client::StreamingClient::new(ip, image_parser, save_stream).map(Streaming::Client)
+        client::StreamingClient::new(ip, config).map(Streaming::Client)
     }
 
-    pub fn new_server(
-        image_parser: impl FnMut(&[u8]) + Send + 'static,
-    ) -> Result<Self, server::StreamingServerError> {
-        server::StreamingServer::new(image_parser).map(Streaming::Server)
+    pub fn new_server(config: ServerConfig) -> Result<Self, server::StreamingServerError> {
+        server::StreamingServer::new(config).map(Streaming::Server)
     }
 
     pub fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
@@ -27,4 +58,46 @@ impl Streaming {
             Streaming::Server(server) => server.start().map_err(|e| e.into()),
         }
     }
+
+    pub fn start_recording(
+        &self,
+        path: &Path,
+        options: &RecordingOptions,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Streaming::Client(client) => {
+                client.start_recording(path, options).map_err(|e| e.into())
+            }
+            Streaming::Server(server) => {
+                server.start_recording(path, options).map_err(|e| e.into())
+            }
+        }
+    }
+
+    pub fn stop_recording(&self) {
+        match self {
+            Streaming::Client(client) => client.stop_recording(),
+            Streaming::Server(server) => server.stop_recording(),
+        }
+    }
+
+    /// Saves the latest frame in `directory`, returns the path of the image
+    pub fn snapshot(
+        &self,
+        directory: &Path,
+        format: SnapshotFormat,
+    ) -> Result<PathBuf, SnapshotError> {
+        match self {
+            Streaming::Client(client) => client.snapshot(directory, format),
+            Streaming::Server(server) => server.snapshot(directory, format),
+        }
+    }
+
+    /// Returns a channel receiving all the events of this session
+    pub fn subscribe(&self) -> Subscription {
+        match self {
+            Streaming::Client(client) => client.subscribe(),
+            Streaming::Server(server) => server.subscribe(),
+        }
+    }
 }
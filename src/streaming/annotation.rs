@@ -0,0 +1,204 @@
+use std::sync::{Arc, Mutex, OnceLock};
+
+use ab_glyph::{FontArc, PxScale};
+use gstreamer::{self as gst, prelude::*};
+use gstreamer_video as gst_video;
+use image::{Rgba, RgbaImage};
+use imageproc::drawing::{draw_filled_circle_mut, draw_line_segment_mut, draw_text_mut};
+
+/// Sizes of the annotations are given for a frame this high and scaled with the stream
+pub const REFERENCE_HEIGHT: f32 = 1080.0;
+/// Text is this many times higher than the line width
+pub const TEXT_SCALE: f32 = 8.0;
+/// Length of the arrow heads, in line widths
+pub const ARROW_HEAD_SCALE: f32 = 5.0;
+
+/// A position on the frame, from (0, 0) top left to (1, 1) bottom right,
+/// so that annotations stay in place whatever the output size
+pub type Point = (f32, f32);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    /// Freehand line through the points
+    Pen(Vec<Point>),
+    Arrow {
+        from: Point,
+        to: Point,
+    },
+    Rectangle {
+        from: Point,
+        to: Point,
+    },
+    /// Text with its top left corner at `at`
+    Text {
+        at: Point,
+        text: String,
+    },
+}
+
+/// A mark the caster draws over the cast, see `StreamingServer::set_annotations`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub shape: Shape,
+    /// RGBA, not premultiplied
+    pub color: [u8; 4],
+    /// Line width in pixels of a `REFERENCE_HEIGHT` frame
+    pub width: f32,
+}
+
+/// The two ends of the head of an arrow pointing to `to`, in the same unit as the points
+pub fn arrow_head(from: (f32, f32), to: (f32, f32), length: f32) -> [(f32, f32); 2] {
+    let angle = (to.1 - from.1).atan2(to.0 - from.0);
+    let spread = std::f32::consts::FRAC_PI_6;
+    [angle + spread, angle - spread].map(|a| (to.0 - length * a.cos(), to.1 - length * a.sin()))
+}
+
+/// Font of the text annotations, embedded so that casting doesn't depend on the ui
+pub(crate) fn font() -> &'static FontArc {
+    static FONT: OnceLock<FontArc> = OnceLock::new();
+    FONT.get_or_init(|| {
+        FontArc::try_from_slice(include_bytes!("fonts/DejaVuSans.ttf"))
+            .expect("the embedded font is valid")
+    })
+}
+
+/// Line as a row of discs, imageproc lines are one pixel wide
+fn draw_line(image: &mut RgbaImage, from: (f32, f32), to: (f32, f32), width: f32, color: Rgba<u8>) {
+    let radius = width / 2.0;
+    if radius < 1.0 {
+        draw_line_segment_mut(image, from, to, color);
+        return;
+    }
+    let length = ((to.0 - from.0).powi(2) + (to.1 - from.1).powi(2)).sqrt();
+    let steps = (length / (radius / 2.0)).ceil().max(1.0) as usize;
+    for i in 0..=steps {
+        let t = i as f32 / steps as f32;
+        let x = from.0 + (to.0 - from.0) * t;
+        let y = from.1 + (to.1 - from.1) * t;
+        draw_filled_circle_mut(image, (x as i32, y as i32), radius as i32, color);
+    }
+}
+
+/// Draws the annotations on a transparent image of the given size
+pub(crate) fn render(annotations: &[Annotation], width: u32, height: u32) -> RgbaImage {
+    let mut image = RgbaImage::new(width, height);
+    let scale = height as f32 / REFERENCE_HEIGHT;
+    let to_pixels = |(x, y): Point| (x * width as f32, y * height as f32);
+
+    for annotation in annotations {
+        let color = Rgba(annotation.color);
+        let line_width = annotation.width * scale;
+        match &annotation.shape {
+            Shape::Pen(points) => {
+                for segment in points.windows(2) {
+                    let (from, to) = (to_pixels(segment[0]), to_pixels(segment[1]));
+                    draw_line(&mut image, from, to, line_width, color);
+                }
+            }
+            Shape::Arrow { from, to } => {
+                let (from, to) = (to_pixels(*from), to_pixels(*to));
+                draw_line(&mut image, from, to, line_width, color);
+                for end in arrow_head(from, to, line_width * ARROW_HEAD_SCALE) {
+                    draw_line(&mut image, to, end, line_width, color);
+                }
+            }
+            Shape::Rectangle { from, to } => {
+                let ((x1, y1), (x2, y2)) = (to_pixels(*from), to_pixels(*to));
+                let corners = [(x1, y1), (x2, y1), (x2, y2), (x1, y2), (x1, y1)];
+                for side in corners.windows(2) {
+                    draw_line(&mut image, side[0], side[1], line_width, color);
+                }
+            }
+            Shape::Text { at, text } => {
+                let (x, y) = to_pixels(*at);
+                let size = PxScale::from(line_width * TEXT_SCALE);
+                draw_text_mut(&mut image, color, x as i32, y as i32, size, font(), text);
+            }
+        }
+    }
+
+    image
+}
+
+#[derive(Default)]
+struct LayerState {
+    annotations: Vec<Annotation>,
+    /// `annotations` rendered for the current frame size
+    composition: Option<(u32, u32, gst_video::VideoOverlayComposition)>,
+}
+
+/// Feeds the annotations to an `overlaycomposition` element, which blends them in every frame
+#[derive(Default)]
+pub(crate) struct AnnotationLayer {
+    state: Mutex<LayerState>,
+}
+
+impl AnnotationLayer {
+    pub fn set(&self, annotations: Vec<Annotation>) {
+        let mut state = self.state.lock().unwrap();
+        state.annotations = annotations;
+        state.composition = None;
+    }
+
+    /// Renders the annotations only when they or the frame size change
+    fn composition(&self, width: u32, height: u32) -> Option<gst_video::VideoOverlayComposition> {
+        let mut state = self.state.lock().unwrap();
+        if state.annotations.is_empty() {
+            return None;
+        }
+        match &state.composition {
+            Some((w, h, composition)) if (*w, *h) == (width, height) => Some(composition.clone()),
+            _ => {
+                let composition = compose(&state.annotations, width, height)?;
+                state.composition = Some((width, height, composition.clone()));
+                Some(composition)
+            }
+        }
+    }
+
+    /// Starts drawing the annotations on `overlay`, an `overlaycomposition` element
+    pub fn attach(self: &Arc<Self>, overlay: &gst::Element) {
+        let layer = self.clone();
+        overlay.connect("draw", false, move |args| {
+            let sample = args[1]
+                .get::<gst::Sample>()
+                .expect("draw signal without sample");
+            let composition = sample
+                .caps()
+                .and_then(|caps| gst_video::VideoInfo::from_caps(caps).ok())
+                .and_then(|info| layer.composition(info.width(), info.height()));
+            Some(composition.to_value())
+        });
+    }
+}
+
+fn compose(
+    annotations: &[Annotation],
+    width: u32,
+    height: u32,
+) -> Option<gst_video::VideoOverlayComposition> {
+    let mut data = render(annotations, width, height).into_raw();
+    // overlay rectangles are ARGB words, that is BGRA bytes on little endian machines
+    for pixel in data.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+
+    let mut buffer = gst::Buffer::from_mut_slice(data);
+    gst_video::VideoMeta::add(
+        buffer.get_mut().unwrap(),
+        gst_video::VideoFrameFlags::empty(),
+        gst_video::VideoFormat::Bgra,
+        width,
+        height,
+    )
+    .ok()?;
+    let rectangle = gst_video::VideoOverlayRectangle::new_raw(
+        &buffer,
+        0,
+        0,
+        width,
+        height,
+        gst_video::VideoOverlayFormatFlags::empty(),
+    );
+    gst_video::VideoOverlayComposition::new(Some(&rectangle)).ok()
+}
@@ -0,0 +1,296 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use ab_glyph::PxScale;
+use gstreamer::{self as gst, prelude::*};
+use gstreamer_app as gst_app;
+use gstreamer_video as gst_video;
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use imageproc::drawing::{draw_text_mut, text_size};
+use thiserror::Error;
+
+use super::annotation::font;
+use super::frame::VideoFrame;
+use super::pipeline::FRAMERATE;
+
+/// Size of the blank screen until the capture size is known
+const DEFAULT_WIDTH: u32 = 1280;
+const DEFAULT_HEIGHT: u32 = 720;
+/// Height of the text of the cards on a `DEFAULT_HEIGHT` frame, scaled with the frame
+const CARD_TEXT_SIZE: f32 = 96.0;
+const CARD_BACKGROUND: [u8; 3] = [32, 32, 32];
+
+#[derive(Error, Debug)]
+pub enum BlankError {
+    #[error("Can't load the image: {0}")]
+    Image(#[from] image::ImageError),
+
+    #[error("No frame to freeze yet")]
+    NoFrame,
+
+    #[error("Invalid frame")]
+    InvalidFrame,
+}
+
+/// What the receivers see while the screen is blanked
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlankContent {
+    /// RGB
+    Color([u8; 3]),
+    /// An image file, e.g. a slide
+    Image(PathBuf),
+    /// A card with a message, like "Be right back"
+    Text(String),
+    /// The last frame sent before blanking
+    FrozenFrame,
+}
+
+impl Default for BlankContent {
+    fn default() -> Self {
+        BlankContent::Color([255, 255, 255])
+    }
+}
+
+impl BlankContent {
+    /// Renders the content at `width`x`height`, `frozen` is the frame for
+    /// `BlankContent::FrozenFrame`. Images keep their aspect ratio, with black bars
+    pub(crate) fn render(
+        &self,
+        width: u32,
+        height: u32,
+        frozen: Option<&VideoFrame>,
+    ) -> Result<RgbaImage, BlankError> {
+        match self {
+            BlankContent::Color([r, g, b]) => {
+                let color = Rgba([*r, *g, *b, 255]);
+                Ok(RgbaImage::from_pixel(width, height, color))
+            }
+            BlankContent::Image(path) => Ok(fit(image::open(path)?.to_rgba8(), width, height)),
+            BlankContent::Text(text) => {
+                let [r, g, b] = CARD_BACKGROUND;
+                let mut image = RgbaImage::from_pixel(width, height, Rgba([r, g, b, 255]));
+                let scale = PxScale::from(CARD_TEXT_SIZE * height as f32 / DEFAULT_HEIGHT as f32);
+                let (text_width, text_height) = text_size(scale, font(), text);
+                let x = (width as i32 - text_width as i32) / 2;
+                let y = (height as i32 - text_height as i32) / 2;
+                let white = Rgba([255, 255, 255, 255]);
+                draw_text_mut(&mut image, white, x, y, scale, font(), text);
+                Ok(image)
+            }
+            BlankContent::FrozenFrame => {
+                let frame = frozen
+                    .ok_or(BlankError::NoFrame)?
+                    .to_rgba_image()
+                    .ok_or(BlankError::InvalidFrame)?;
+                Ok(fit(frame, width, height))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for BlankContent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            BlankContent::Color(_) => "Color",
+            BlankContent::Image(_) => "Image",
+            BlankContent::Text(_) => "Text card",
+            BlankContent::FrozenFrame => "Last frame",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// `image` scaled to fit `width`x`height` and centered on black
+fn fit(image: RgbaImage, width: u32, height: u32) -> RgbaImage {
+    if image.dimensions() == (width, height) {
+        return image;
+    }
+    let scale = f64::min(
+        width as f64 / image.width() as f64,
+        height as f64 / image.height() as f64,
+    );
+    let scaled_width = ((image.width() as f64 * scale).round() as u32).clamp(1, width);
+    let scaled_height = ((image.height() as f64 * scale).round() as u32).clamp(1, height);
+    let scaled = imageops::resize(&image, scaled_width, scaled_height, FilterType::Triangle);
+
+    let mut fitted = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+    let x = (width - scaled_width) / 2;
+    let y = (height - scaled_height) / 2;
+    imageops::overlay(&mut fitted, &scaled, x as i64, y as i64);
+    fitted
+}
+
+/// An appsrc repeating the blank content at `FRAMERATE` and at the size of the capture,
+/// the content can be replaced at any time
+pub(crate) struct BlankSource {
+    appsrc: gst_app::AppSrc,
+    state: Mutex<BlankState>,
+}
+
+struct BlankState {
+    content: BlankContent,
+    /// The frame of `BlankContent::FrozenFrame`
+    frozen: Option<VideoFrame>,
+    width: u32,
+    height: u32,
+    /// The rendered content as a buffer, with its caps
+    image: (gst::Caps, gst::Buffer),
+}
+
+impl BlankSource {
+    /// Shows `content` at a default size until `set_size` gives the capture size
+    pub fn new(content: BlankContent) -> Result<Arc<Self>, BlankError> {
+        let image = content.render(DEFAULT_WIDTH, DEFAULT_HEIGHT, None)?;
+        let appsrc = gst_app::AppSrc::builder().format(gst::Format::Time).build();
+        let source = Arc::new(Self {
+            appsrc: appsrc.clone(),
+            state: Mutex::new(BlankState {
+                content,
+                frozen: None,
+                width: DEFAULT_WIDTH,
+                height: DEFAULT_HEIGHT,
+                image: to_buffer(image),
+            }),
+        });
+
+        // timestamped like a videotestsrc, the selector holds the frames back while not active
+        let weak = Arc::downgrade(&source);
+        let duration = gst::ClockTime::SECOND / FRAMERATE as u64;
+        let mut index = 0u64;
+        appsrc.set_callbacks(
+            gst_app::AppSrcCallbacks::builder()
+                .need_data(move |appsrc, _| {
+                    let Some(source) = weak.upgrade() else {
+                        return;
+                    };
+                    let (caps, buffer) = source.state.lock().unwrap().image.clone();
+                    let mut buffer = buffer.copy();
+                    {
+                        let buffer = buffer.get_mut().unwrap();
+                        buffer.set_pts(duration * index);
+                        buffer.set_duration(duration);
+                    }
+                    appsrc.set_caps(Some(&caps));
+                    let _ = appsrc.push_buffer(buffer);
+                    index += 1;
+                })
+                .build(),
+        );
+
+        Ok(source)
+    }
+
+    pub fn element(&self) -> gst::Element {
+        self.appsrc.clone().upcast()
+    }
+
+    /// Replaces the content, `frozen` is the frame for `BlankContent::FrozenFrame`
+    pub fn set(&self, content: BlankContent, frozen: Option<VideoFrame>) -> Result<(), BlankError> {
+        let mut state = self.state.lock().unwrap();
+        let image = content.render(state.width, state.height, frozen.as_ref())?;
+        state.content = content;
+        state.frozen = frozen;
+        state.image = to_buffer(image);
+        Ok(())
+    }
+
+    /// Renders the content again at the new size of the capture
+    pub fn set_size(&self, width: u32, height: u32) {
+        let mut state = self.state.lock().unwrap();
+        if (state.width, state.height) == (width, height) {
+            return;
+        }
+        match state.content.render(width, height, state.frozen.as_ref()) {
+            Ok(image) => {
+                state.width = width;
+                state.height = height;
+                state.image = to_buffer(image);
+            }
+            Err(e) => println!("Can't resize the blank screen: {}", e),
+        }
+    }
+}
+
+fn to_buffer(image: RgbaImage) -> (gst::Caps, gst::Buffer) {
+    let caps = gst_video::VideoCapsBuilder::new()
+        .format(gst_video::VideoFormat::Rgba)
+        .width(image.width() as i32)
+        .height(image.height() as i32)
+        .framerate(gst::Fraction::new(FRAMERATE, 1))
+        .build();
+    (caps, gst::Buffer::from_mut_slice(image.into_raw()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::frame::FrameFormat;
+
+    const SIZES: [(u32, u32); 3] = [(1920, 1080), (640, 480), (1080, 1920)];
+
+    fn red_frame(width: u32, height: u32) -> VideoFrame {
+        VideoFrame {
+            format: FrameFormat::Rgba,
+            width,
+            height,
+            stride: width as usize * 4,
+            data: [255, 0, 0, 255].repeat((width * height) as usize),
+        }
+    }
+
+    #[test]
+    fn cards_have_the_requested_size() {
+        let contents = [
+            BlankContent::Color([0, 128, 255]),
+            BlankContent::Text("Be right back".to_string()),
+        ];
+        for content in contents {
+            for (width, height) in SIZES {
+                let image = content.render(width, height, None).unwrap();
+                assert_eq!(image.dimensions(), (width, height), "{}", content);
+            }
+        }
+    }
+
+    #[test]
+    fn frozen_frame_is_letterboxed_to_the_requested_size() {
+        let frame = red_frame(160, 90);
+        let image = BlankContent::FrozenFrame
+            .render(320, 320, Some(&frame))
+            .unwrap();
+        assert_eq!(image.dimensions(), (320, 320));
+        // scaled to 320x180, with black bars above and below
+        assert_eq!(*image.get_pixel(160, 10), Rgba([0, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(160, 160), Rgba([255, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(160, 310), Rgba([0, 0, 0, 255]));
+
+        let image = BlankContent::FrozenFrame
+            .render(160, 90, Some(&frame))
+            .unwrap();
+        assert_eq!(image, frame.to_rgba_image().unwrap());
+    }
+
+    #[test]
+    fn images_fit_the_requested_size() {
+        let path =
+            std::env::temp_dir().join(format!("rust_streamer_blank_{}.png", std::process::id()));
+        RgbaImage::from_pixel(30, 40, Rgba([0, 255, 0, 255]))
+            .save(&path)
+            .unwrap();
+        let content = BlankContent::Image(path.clone());
+        for (width, height) in SIZES {
+            let image = content.render(width, height, None).unwrap();
+            assert_eq!(image.dimensions(), (width, height));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn freezing_needs_a_frame() {
+        assert!(matches!(
+            BlankContent::FrozenFrame.render(640, 480, None),
+            Err(BlankError::NoFrame)
+        ));
+    }
+}
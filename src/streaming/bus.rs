@@ -0,0 +1,84 @@
+use gstreamer::{self as gst, prelude::*};
+use thiserror::Error;
+
+use super::event::{EventBus, StreamingEvent};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineErrorKind {
+    /// An element or plugin needed by the pipeline is not installed
+    MissingPlugin,
+    /// A device, file or socket could not be used
+    Resource,
+    /// The media could not be decoded, encoded or muxed
+    Stream,
+    /// An external library (e.g. the encoder) failed
+    Library,
+    Core,
+    Other,
+}
+
+/// Error or warning posted on the bus by an element of the pipeline
+#[derive(Error, Debug, Clone)]
+#[error("{element}: {message}")]
+pub struct PipelineError {
+    pub kind: PipelineErrorKind,
+    /// Path of the element that posted the message
+    pub element: String,
+    pub message: String,
+    pub debug: Option<String>,
+}
+
+impl PipelineError {
+    fn new(
+        src: Option<&gst::Object>,
+        error: &gst::glib::Error,
+        debug: Option<gst::glib::GString>,
+    ) -> Self {
+        let kind = if error.kind::<gst::CoreError>() == Some(gst::CoreError::MissingPlugin) {
+            PipelineErrorKind::MissingPlugin
+        } else if error.is::<gst::ResourceError>() {
+            PipelineErrorKind::Resource
+        } else if error.is::<gst::StreamError>() {
+            PipelineErrorKind::Stream
+        } else if error.is::<gst::LibraryError>() {
+            PipelineErrorKind::Library
+        } else if error.is::<gst::CoreError>() {
+            PipelineErrorKind::Core
+        } else {
+            PipelineErrorKind::Other
+        };
+
+        Self {
+            kind,
+            element: src
+                .map(|s| s.path_string().to_string())
+                .unwrap_or_else(|| "pipeline".to_string()),
+            message: error.to_string(),
+            debug: debug.map(|d| d.to_string()),
+        }
+    }
+}
+
+/// Forwards errors and warnings of the pipeline as `StreamingEvent`s.
+/// Only EOS messages are kept on the bus, for whoever waits on them: errors are
+/// dropped once forwarded, so that a stale one doesn't end a later wait, and every
+/// other message is dropped too. Subscribe to the `EventBus` instead of reading the bus.
+pub(crate) fn watch(pipeline: &gst::Pipeline, events: EventBus) {
+    let bus = pipeline.bus().unwrap();
+    bus.set_sync_handler(move |_, msg| match msg.view() {
+        gst::MessageView::Error(err) => {
+            let error = PipelineError::new(err.src(), &err.error(), err.debug());
+            println!("Pipeline error: {} ({:?})", error, error.debug);
+            events.emit(StreamingEvent::Error(error));
+            gst::BusSyncReply::Drop
+        }
+        gst::MessageView::Warning(warning) => {
+            let error = PipelineError::new(warning.src(), &warning.error(), warning.debug());
+            println!("Pipeline warning: {} ({:?})", error, error.debug);
+            events.emit(StreamingEvent::Warning(error));
+            gst::BusSyncReply::Drop
+        }
+        gst::MessageView::Eos(..) => gst::BusSyncReply::Pass,
+        _ => gst::BusSyncReply::Drop,
+    });
+}
@@ -0,0 +1,360 @@
+use gstreamer as gst;
+
+use super::pipeline::VideoEncoder;
+use super::recording::Container;
+use super::source::SourceKind;
+use super::watermark::WatermarkKind;
+
+/// What a GStreamer element is needed for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// One of the capture sources
+    Source(SourceKind),
+    /// Sending the encoded stream to the receivers
+    Cast,
+    /// One of the alternative H264 encoders
+    Encoder(VideoEncoder),
+    Receive,
+    /// One of the H264 decoders `decodebin` can pick, any of them is enough
+    Decoder,
+    Record,
+    /// Splitting the recordings in segments
+    Segmentation,
+    /// Playing the saved recordings
+    Play,
+    /// One of the alternative recording containers
+    Container(Container),
+    /// Optional overlays stamped on the cast
+    Watermark(WatermarkKind),
+    /// JPEG encoding of the frames, only for `FrameFormat::Jpeg`
+    Jpeg,
+    /// Elements used by every pipeline
+    Common,
+}
+
+/// An element that is not installed, with the plugin and package providing it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingElement {
+    pub element: &'static str,
+    pub plugin: &'static str,
+    pub package: &'static str,
+    pub feature: Feature,
+}
+
+impl std::fmt::Display for MissingElement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (plugin \"{}\" from {})",
+            self.element, self.plugin, self.package
+        )
+    }
+}
+
+/// (feature, element, plugin, package)
+type Requirement = (Feature, &'static str, &'static str, &'static str);
+
+#[cfg(target_os = "windows")]
+#[rustfmt::skip]
+const PLATFORM_REQUIREMENTS: &[Requirement] = &[
+    (Feature::Source(SourceKind::Screen), "d3d11screencapturesrc", "d3d11", "gst-plugins-bad"),
+    (Feature::Source(SourceKind::Camera), "mfvideosrc", "mediafoundation", "gst-plugins-bad"),
+];
+#[cfg(target_os = "linux")]
+#[rustfmt::skip]
+const PLATFORM_REQUIREMENTS: &[Requirement] = &[
+    (Feature::Source(SourceKind::Screen), "ximagesrc", "ximagesrc", "gst-plugins-good"),
+    (Feature::Source(SourceKind::Window), "ximagesrc", "ximagesrc", "gst-plugins-good"),
+    (Feature::Source(SourceKind::PipeWire), "pipewiresrc", "pipewire", "pipewire-gstreamer"),
+    (Feature::Source(SourceKind::PipeWire), "videorate", "videorate", "gst-plugins-base"),
+    (Feature::Source(SourceKind::Camera), "v4l2src", "video4linux2", "gst-plugins-good"),
+];
+#[cfg(target_os = "macos")]
+#[rustfmt::skip]
+const PLATFORM_REQUIREMENTS: &[Requirement] = &[
+    (Feature::Source(SourceKind::Screen), "avfvideosrc", "applemedia", "gst-plugins-bad"),
+    (Feature::Source(SourceKind::Screen), "videocrop", "videocrop", "gst-plugins-good"),
+    (Feature::Source(SourceKind::Camera), "avfvideosrc", "applemedia", "gst-plugins-bad"),
+];
+
+/// Suggested when no H264 decoder is installed
+const DEFAULT_DECODER: &str = "avdec_h264";
+
+#[rustfmt::skip]
+const REQUIREMENTS: &[Requirement] = &[
+    (Feature::Common, "queue", "coreelements", "gstreamer"),
+    (Feature::Common, "tee", "coreelements", "gstreamer"),
+    (Feature::Common, "videoconvert", "videoconvertscale", "gst-plugins-base"),
+    (Feature::Common, "appsink", "app", "gst-plugins-base"),
+    (Feature::Jpeg, "jpegenc", "jpeg", "gst-plugins-good"),
+    (Feature::Source(SourceKind::Camera), "videorate", "videorate", "gst-plugins-base"),
+    (Feature::Source(SourceKind::TestPattern), "videotestsrc", "videotestsrc", "gst-plugins-base"),
+    (Feature::Source(SourceKind::File), "uridecodebin", "playback", "gst-plugins-base"),
+    (Feature::Source(SourceKind::File), "videorate", "videorate", "gst-plugins-base"),
+    (Feature::Source(SourceKind::Slideshow), "appsrc", "app", "gst-plugins-base"),
+    (Feature::Source(SourceKind::Slideshow), "videoscale", "videoconvertscale", "gst-plugins-base"),
+    (Feature::Source(SourceKind::Slideshow), "videorate", "videorate", "gst-plugins-base"),
+    (Feature::Cast, "input-selector", "coreelements", "gstreamer"),
+    (Feature::Cast, "valve", "coreelements", "gstreamer"),
+    (Feature::Cast, "appsrc", "app", "gst-plugins-base"),
+    (Feature::Cast, "overlaycomposition", "overlaycomposition", "gst-plugins-base"),
+    (Feature::Cast, "videorate", "videorate", "gst-plugins-base"),
+    (Feature::Cast, "videoscale", "videoconvertscale", "gst-plugins-base"),
+    (Feature::Cast, "rtph264pay", "rtp", "gst-plugins-good"),
+    (Feature::Cast, "multiudpsink", "udp", "gst-plugins-good"),
+    (Feature::Encoder(VideoEncoder::X264), "x264enc", "x264", "gst-plugins-ugly"),
+    (Feature::Encoder(VideoEncoder::OpenH264), "openh264enc", "openh264", "gst-plugins-bad"),
+    (Feature::Encoder(VideoEncoder::Nvenc), "nvh264enc", "nvcodec", "gst-plugins-bad"),
+    (Feature::Encoder(VideoEncoder::Vaapi), "vaapih264enc", "vaapi", "gstreamer-vaapi"),
+    (Feature::Receive, "udpsrc", "udp", "gst-plugins-good"),
+    (Feature::Receive, "rtph264depay", "rtp", "gst-plugins-good"),
+    (Feature::Receive, "decodebin", "playback", "gst-plugins-base"),
+    (Feature::Decoder, "avdec_h264", "libav", "gst-libav"),
+    (Feature::Decoder, "openh264dec", "openh264", "gst-plugins-bad"),
+    (Feature::Decoder, "vaapih264dec", "vaapi", "gstreamer-vaapi"),
+    (Feature::Decoder, "nvh264dec", "nvcodec", "gst-plugins-bad"),
+    (Feature::Play, "uridecodebin", "playback", "gst-plugins-base"),
+    (Feature::Record, "h264parse", "videoparsersbad", "gst-plugins-bad"),
+    (Feature::Container(Container::Mp4), "mp4mux", "isomp4", "gst-plugins-good"),
+    (Feature::Container(Container::FragmentedMp4), "mp4mux", "isomp4", "gst-plugins-good"),
+    (Feature::Container(Container::Matroska), "matroskamux", "matroska", "gst-plugins-good"),
+    (Feature::Container(Container::MpegTs), "mpegtsmux", "mpegtsmux", "gst-plugins-bad"),
+    (Feature::Record, "filesink", "coreelements", "gstreamer"),
+    (Feature::Segmentation, "splitmuxsink", "multifile", "gst-plugins-good"),
+    (Feature::Watermark(WatermarkKind::Text), "textoverlay", "pango", "gst-plugins-base"),
+    (Feature::Watermark(WatermarkKind::Clock), "clockoverlay", "pango", "gst-plugins-base"),
+    (Feature::Watermark(WatermarkKind::Image), "gdkpixbufoverlay", "gdkpixbuf", "gst-plugins-good"),
+];
+
+/// Result of probing the GStreamer registry for the elements used by the pipelines
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    missing: Vec<MissingElement>,
+}
+
+impl Capabilities {
+    pub(crate) fn probe() -> Self {
+        let missing = REQUIREMENTS
+            .iter()
+            .chain(PLATFORM_REQUIREMENTS)
+            .filter(|(_, element, _, _)| gst::ElementFactory::find(element).is_none())
+            .map(|&(feature, element, plugin, package)| MissingElement {
+                element,
+                plugin,
+                package,
+                feature,
+            })
+            .collect();
+
+        Self { missing }
+    }
+
+    /// Every missing element that prevents casting the screen, receiving or recording
+    pub fn missing(&self) -> Vec<&MissingElement> {
+        let any_encoder = !self.encoders().is_empty();
+        let any_container = !self.containers().is_empty();
+        let any_decoder = self.has_decoder();
+        self.missing
+            .iter()
+            .filter(|m| match m.feature {
+                // encoders, decoders and containers are alternatives, only the default one is suggested
+                Feature::Encoder(encoder) => !any_encoder && encoder == VideoEncoder::default(),
+                Feature::Decoder => !any_decoder && m.element == DEFAULT_DECODER,
+                Feature::Container(container) => {
+                    !any_container && container == Container::default()
+                }
+                // other sources, the watermarks, the segments and JPEG frames are optional
+                Feature::Source(kind) => kind == SourceKind::Screen,
+                Feature::Watermark(_) | Feature::Segmentation | Feature::Jpeg => false,
+                _ => true,
+            })
+            .collect()
+    }
+
+    pub fn is_missing(&self, feature: Feature) -> bool {
+        self.missing.iter().any(|m| m.feature == feature)
+    }
+
+    /// The H264 encoders that are installed
+    pub fn encoders(&self) -> Vec<VideoEncoder> {
+        VideoEncoder::ALL
+            .into_iter()
+            .filter(|&encoder| !self.is_missing(Feature::Encoder(encoder)))
+            .collect()
+    }
+
+    /// True if at least one H264 decoder is installed
+    pub fn has_decoder(&self) -> bool {
+        let decoders = REQUIREMENTS
+            .iter()
+            .filter(|(feature, _, _, _)| *feature == Feature::Decoder)
+            .count();
+        let missing = self
+            .missing
+            .iter()
+            .filter(|m| m.feature == Feature::Decoder)
+            .count();
+        missing < decoders
+    }
+
+    /// The recording containers whose muxer is installed
+    pub fn containers(&self) -> Vec<Container> {
+        Container::ALL
+            .into_iter()
+            .filter(|&container| !self.is_missing(Feature::Container(container)))
+            .collect()
+    }
+
+    /// The watermarks whose element is installed
+    pub fn watermarks(&self) -> Vec<WatermarkKind> {
+        WatermarkKind::ALL
+            .into_iter()
+            .filter(|&kind| !self.is_missing(Feature::Watermark(kind)))
+            .collect()
+    }
+
+    /// The capture sources that can be used
+    pub fn sources(&self) -> Vec<SourceKind> {
+        SourceKind::ALL
+            .into_iter()
+            // window and PipeWire capture are Linux only
+            .filter(|&kind| {
+                !matches!(kind, SourceKind::Window | SourceKind::PipeWire)
+                    || cfg!(target_os = "linux")
+            })
+            .filter(|&kind| !self.is_missing(Feature::Source(kind)))
+            .collect()
+    }
+
+    pub fn can_capture(&self) -> bool {
+        !self.is_missing(Feature::Source(SourceKind::Screen))
+    }
+
+    /// True if a stream can be sent, provided that the chosen source is available
+    pub fn can_cast(&self) -> bool {
+        !self.is_missing(Feature::Common)
+            && !self.is_missing(Feature::Cast)
+            && !self.encoders().is_empty()
+    }
+
+    pub fn can_receive(&self) -> bool {
+        !self.is_missing(Feature::Common)
+            && !self.is_missing(Feature::Receive)
+            && self.has_decoder()
+    }
+
+    pub fn can_play(&self) -> bool {
+        !self.is_missing(Feature::Common) && !self.is_missing(Feature::Play)
+    }
+
+    /// True if the encoded stream can be saved, on the caster or on a receiver
+    pub fn can_record(&self) -> bool {
+        !self.is_missing(Feature::Common)
+            && !self.is_missing(Feature::Record)
+            && !self.containers().is_empty()
+    }
+
+    /// True if the recordings can be split in segments, see `recording::Segmentation`
+    pub fn can_segment(&self) -> bool {
+        self.can_record() && !self.is_missing(Feature::Segmentation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Capabilities without `elements`
+    fn without(elements: &[&str]) -> Capabilities {
+        let missing = REQUIREMENTS
+            .iter()
+            .chain(PLATFORM_REQUIREMENTS)
+            .filter(|(_, element, _, _)| elements.contains(element))
+            .map(|&(feature, element, plugin, package)| MissingElement {
+                element,
+                plugin,
+                package,
+                feature,
+            })
+            .collect();
+        Capabilities { missing }
+    }
+
+    fn missing_elements(capabilities: &Capabilities) -> Vec<&'static str> {
+        capabilities.missing().iter().map(|m| m.element).collect()
+    }
+
+    #[test]
+    fn nothing_missing() {
+        let capabilities = without(&[]);
+        assert!(capabilities.missing().is_empty());
+        assert!(capabilities.can_cast() && capabilities.can_receive() && capabilities.can_record());
+    }
+
+    #[test]
+    fn required_elements_are_reported() {
+        let capabilities = without(&["queue", "rtph264pay"]);
+        assert_eq!(missing_elements(&capabilities), ["queue", "rtph264pay"]);
+        assert!(!capabilities.can_cast());
+    }
+
+    #[test]
+    fn one_encoder_is_enough() {
+        let capabilities = without(&["x264enc", "nvh264enc"]);
+        assert!(capabilities.missing().is_empty());
+        assert_eq!(
+            capabilities.encoders(),
+            [VideoEncoder::OpenH264, VideoEncoder::Vaapi]
+        );
+
+        // only the default one is suggested
+        let capabilities = without(&["x264enc", "openh264enc", "nvh264enc", "vaapih264enc"]);
+        assert_eq!(missing_elements(&capabilities), ["x264enc"]);
+        assert!(!capabilities.can_cast());
+    }
+
+    #[test]
+    fn one_decoder_is_enough() {
+        let capabilities = without(&["avdec_h264", "vaapih264dec"]);
+        assert!(capabilities.missing().is_empty());
+        assert!(capabilities.has_decoder());
+
+        let capabilities = without(&["avdec_h264", "openh264dec", "vaapih264dec", "nvh264dec"]);
+        assert_eq!(missing_elements(&capabilities), [DEFAULT_DECODER]);
+        assert!(!capabilities.has_decoder());
+        assert!(!capabilities.can_receive());
+    }
+
+    #[test]
+    fn one_container_is_enough() {
+        let capabilities = without(&["mp4mux"]);
+        assert!(capabilities.missing().is_empty());
+        assert_eq!(
+            capabilities.containers(),
+            [Container::Matroska, Container::MpegTs]
+        );
+
+        let capabilities = without(&["mp4mux", "matroskamux", "mpegtsmux"]);
+        let missing = capabilities.missing();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].feature, Feature::Container(Container::default()));
+        assert!(!capabilities.can_record());
+    }
+
+    #[test]
+    fn optional_elements_are_not_reported() {
+        let capabilities = without(&[
+            "jpegenc",
+            "splitmuxsink",
+            "textoverlay",
+            "clockoverlay",
+            "gdkpixbufoverlay",
+            "videotestsrc",
+        ]);
+        assert!(capabilities.missing().is_empty());
+        assert!(capabilities.is_missing(Feature::Jpeg));
+        assert!(capabilities.can_cast() && capabilities.can_receive());
+        assert!(capabilities.can_record() && !capabilities.can_segment());
+        assert!(capabilities.watermarks().is_empty());
+    }
+}
@@ -1,19 +1,24 @@
-use byte_slice_cast::*;
 use std::{
     io,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex,
     },
+    time::{Duration, Instant},
 };
 
-use crate::connection::client::ConnectionClient;
-use gstreamer::{self as gst, element_error, glib, prelude::*};
+use super::bus;
+use super::event::{EventBus, StatsCounter, StreamingEvent, Subscription};
+use super::frame::pull_frame;
+use super::pipeline::{self, BuildError, ClientConfig, ClientPipeline};
+use super::recording::{Recorder, RecordingOptions};
+use super::snapshot::{self, LatestFrame, SnapshotError, SnapshotFormat};
+use crate::connection::{client::ConnectionClient, ControlMessage};
+use gstreamer::{self as gst, glib, prelude::*};
 use gstreamer_app as gst_app;
 use thiserror::Error;
 
-use chrono::prelude::*;
-
 #[derive(Error, Debug)]
 pub enum StreamingClientError {
     #[error("GStreamer init error: {0}")]
@@ -22,94 +27,123 @@ pub enum StreamingClientError {
     #[error("GStreamer element error: {0}")]
     GStreamerElementCreationError(#[from] glib::BoolError),
 
+    #[error("Pipeline build error: {0}")]
+    PipelineBuildError(#[from] BuildError),
+
     #[error("GStreamer state change error: {0}")]
     GStreamerStateChangeError(#[from] gst::StateChangeError),
 
     #[error("Websocket error: {0}")]
     WebsocketError(#[from] io::Error),
+
+    #[error("Already recording")]
+    AlreadyRecording,
 }
 
-pub struct StreamingClient {
-    pipeline: Arc<gst::Pipeline>,
-    _connection_client: ConnectionClient,
-    connected: Arc<AtomicBool>,
+/// Maximum time to wait for a recording to be finalized when the client stops
+const RECORDING_TIMEOUT: Duration = Duration::from_secs(5);
+/// The stream is considered stalled if no frame is decoded for this long
+const STALL_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// State shared between the client and the connection callbacks
+struct Shared {
+    pipeline: gst::Pipeline,
+    tee: gst::Element,
+    connected: AtomicBool,
+    finished: Mutex<bool>,
+    recorder: Mutex<Option<Recorder>>,
+    events: EventBus,
 }
 
-impl StreamingClient {
-    pub fn new<T: AsRef<str>>(
-        ip: T,
-        mut image_parser: impl FnMut(&[u8]) + Send + 'static,
-        save_stream: bool,
-    ) -> Result<Self, StreamingClientError> {
-        gst::init()?;
+impl Shared {
+    /// Finalizes the recording, if any, waiting for it at most `RECORDING_TIMEOUT`,
+    /// and stops the pipeline. Runs only once.
+    fn finish(&self) {
+        let mut finished = self.finished.lock().unwrap();
+        if *finished {
+            return;
+        }
 
-        let mut pipeline_string = "udpsrc port=9001 !
-        application/x-rtp, media=video, clock-rate=90000, encoding-name=H264, payload=96 ! rtph264depay ! tee name=t ! queue ! decodebin !
-        videoconvert ! jpegenc ! appsink name=s max-buffers=1 caps=image/jpeg".to_string();
+        if let Some(recorder) = self.recorder.lock().unwrap().take() {
+            match recorder.detach_and_wait(RECORDING_TIMEOUT) {
+                Some(path) => self.events.emit(StreamingEvent::RecordingFinished(path)),
+                None => println!("Timed out finalizing the recording"),
+            }
+        }
+        let _ = self.pipeline.set_state(gst::State::Null);
+        *finished = true;
+    }
 
-        if save_stream {
-            pipeline_string.push_str(&format!(
-                " t. ! queue ! h264parse ! mp4mux ! filesink location=./stream{}.mp4",
-                Local::now().format("%Y%m%d_%H%M%S")
-            ));
+    /// Called when the caster goes away, either gracefully or not
+    fn disconnect(&self) {
+        self.finish();
+        if self.connected.swap(false, Ordering::Relaxed) {
+            self.events.emit(StreamingEvent::Disconnected);
         }
+    }
+}
 
-        let pipeline = gst::parse::launch(&pipeline_string)?
-            .dynamic_cast::<gst::Pipeline>()
-            .unwrap();
+pub struct StreamingClient {
+    shared: Arc<Shared>,
+    _connection_client: ConnectionClient,
+    last_frame: Arc<Mutex<Instant>>,
+    latest_frame: LatestFrame,
+}
 
-        let sink: gst_app::AppSink = pipeline.by_name("s").unwrap().dynamic_cast().unwrap();
+impl StreamingClient {
+    pub fn new<T: AsRef<str>>(ip: T, config: ClientConfig) -> Result<Self, StreamingClientError> {
+        gst::init()?;
 
-        let pipeline = Arc::new(pipeline);
-        let connected = Arc::new(AtomicBool::new(true));
+        let ClientPipeline {
+            pipeline,
+            tee,
+            appsink,
+        } = pipeline::build_client(&config)?;
 
-        let pipeline_clone = pipeline.clone();
-        let connected_clone = connected.clone();
-        let connection_client = ConnectionClient::new(ip, move || {
-            pipeline_clone.send_event(gst::event::Eos::new());
-            pipeline_clone
-                .bus()
-                .unwrap()
-                .timed_pop_filtered(gst::ClockTime::NONE, &[gst::MessageType::Eos]);
-            let _ = pipeline_clone.set_state(gst::State::Null);
-            connected_clone.store(false, Ordering::Relaxed);
-        })?;
+        let events = EventBus::default();
+        bus::watch(&pipeline, events.clone());
 
-        sink.set_callbacks(
+        let shared = Arc::new(Shared {
+            pipeline,
+            tee,
+            connected: AtomicBool::new(true),
+            finished: Mutex::new(false),
+            recorder: Mutex::new(None),
+            events: events.clone(),
+        });
+
+        let shared_clone = shared.clone();
+        let shared_clone2 = shared.clone();
+        let connection_client = ConnectionClient::new(
+            ip,
+            move |message| match message {
+                ControlMessage::StreamEnding => {
+                    println!("Stream ending");
+                    shared_clone.disconnect();
+                }
+                _ => {}
+            },
+            move || shared_clone2.disconnect(),
+        )?;
+
+        let last_frame = Arc::new(Mutex::new(Instant::now()));
+        let last_frame_clone = last_frame.clone();
+        let latest_frame = LatestFrame::default();
+        let latest_frame_clone = latest_frame.clone();
+        let mut stats = StatsCounter::new();
+
+        let frame_format = config.frame_format;
+        appsink.set_callbacks(
             gst_app::AppSinkCallbacks::builder()
                 .new_sample(move |appsink| {
-                    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
-                    let buffer = sample.buffer().ok_or_else(|| {
-                        element_error!(
-                            appsink,
-                            gst::ResourceError::Failed,
-                            ("Failed to get buffer from appsink")
-                        );
-
-                        gst::FlowError::Error
-                    })?;
-
-                    let map = buffer.map_readable().map_err(|_| {
-                        element_error!(
-                            appsink,
-                            gst::ResourceError::Failed,
-                            ("Failed to map buffer readable")
-                        );
-
-                        gst::FlowError::Error
-                    })?;
-
-                    let samples = map.as_slice_of::<u8>().map_err(|_| {
-                        element_error!(
-                            appsink,
-                            gst::ResourceError::Failed,
-                            ("Failed to interpret buffer as bytes")
-                        );
-
-                        gst::FlowError::Error
-                    })?;
-
-                    image_parser(samples);
+                    let frame = Arc::new(pull_frame(appsink, frame_format)?);
+
+                    *last_frame_clone.lock().unwrap() = Instant::now();
+                    *latest_frame_clone.lock().unwrap() = Some(frame.clone());
+                    events.emit(StreamingEvent::FrameReady(frame));
+                    if let Some(stats) = stats.frame() {
+                        events.emit(StreamingEvent::StatsUpdated(stats));
+                    }
 
                     Ok(gst::FlowSuccess::Ok)
                 })
@@ -117,30 +151,77 @@ impl StreamingClient {
         );
 
         Ok(Self {
-            pipeline,
+            shared,
             _connection_client: connection_client,
-            connected,
+            last_frame,
+            latest_frame,
         })
     }
 
     pub fn start(&self) -> Result<(), StreamingClientError> {
-        Ok(self.pipeline.set_state(gst::State::Playing).map(|_| ())?)
+        *self.last_frame.lock().unwrap() = Instant::now();
+        self.shared.pipeline.set_state(gst::State::Playing)?;
+        Ok(())
+    }
+
+    /// Saves the received stream to `path` (usually `options.path(..)`) from now on,
+    /// without decoding it again
+    pub fn start_recording(
+        &self,
+        path: &Path,
+        options: &RecordingOptions,
+    ) -> Result<(), StreamingClientError> {
+        let mut recorder = self.shared.recorder.lock().unwrap();
+        if recorder.is_some() {
+            return Err(StreamingClientError::AlreadyRecording);
+        }
+        let new_recorder =
+            Recorder::attach(&self.shared.pipeline, &self.shared.tee, path, options)?;
+        self.shared.events.emit(StreamingEvent::RecordingStarted(
+            new_recorder.path().to_path_buf(),
+        ));
+        *recorder = Some(new_recorder);
+        Ok(())
+    }
+
+    /// Finalizes the recording while the reception goes on,
+    /// `StreamingEvent::RecordingFinished` is sent once the file is complete
+    pub fn stop_recording(&self) {
+        if let Some(recorder) = self.shared.recorder.lock().unwrap().take() {
+            let events = self.shared.events.clone();
+            recorder.detach(move |path| events.emit(StreamingEvent::RecordingFinished(path)));
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.shared.recorder.lock().unwrap().is_some()
+    }
+
+    /// Saves the last received frame in `directory`, returns the path of the image
+    pub fn snapshot(
+        &self,
+        directory: &Path,
+        format: SnapshotFormat,
+    ) -> Result<PathBuf, SnapshotError> {
+        snapshot::save(&self.latest_frame, directory, format)
     }
 
     pub fn is_connected(&self) -> bool {
-        self.connected.load(Ordering::Relaxed)
+        self.shared.connected.load(Ordering::Relaxed)
+    }
+
+    /// True if the caster is still connected but no frame arrived recently
+    pub fn is_stalled(&self) -> bool {
+        self.is_connected() && self.last_frame.lock().unwrap().elapsed() > STALL_TIMEOUT
+    }
+
+    pub fn subscribe(&self) -> Subscription {
+        self.shared.events.subscribe()
     }
 }
 
 impl Drop for StreamingClient {
     fn drop(&mut self) {
-        if self.is_connected() {
-            self.pipeline.send_event(gst::event::Eos::new());
-            self.pipeline
-                .bus()
-                .unwrap()
-                .timed_pop_filtered(gst::ClockTime::NONE, &[gst::MessageType::Eos]);
-        }
-        let _ = self.pipeline.set_state(gst::State::Null);
+        self.shared.finish();
     }
 }
@@ -0,0 +1,196 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvError, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::bus::PipelineError;
+use super::frame::VideoFrame;
+
+/// Everything that happens during a streaming session, for both caster and receiver.
+#[derive(Debug, Clone)]
+pub enum StreamingEvent {
+    /// A new preview frame, in the format chosen when creating the session
+    FrameReady(Arc<VideoFrame>),
+    /// A receiver connected to the caster (its ip)
+    ReceiverJoined(String),
+    /// A receiver left the caster (its ip)
+    ReceiverLeft(String),
+    /// A receiver (its ip) was given a simulcast layer, an index of `ServerConfig::layers`
+    ReceiverLayerChanged(String, usize),
+    /// The caster ended the stream or the connection was lost
+    Disconnected,
+    /// A pipeline element failed, the session can't continue
+    Error(PipelineError),
+    /// A pipeline element reported a non fatal problem
+    Warning(PipelineError),
+    StatsUpdated(StreamingStats),
+    RecordingStarted(PathBuf),
+    RecordingFinished(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StreamingStats {
+    /// Frames shown since the start of the session
+    pub frames: u64,
+    pub fps: f32,
+    /// Connected receivers, always 0 on the receiver side
+    pub receivers: usize,
+}
+
+/// Frames queued for a subscriber, newer frames are dropped until it catches up
+const MAX_PENDING_FRAMES: usize = 2;
+
+struct Subscriber {
+    tx: Sender<StreamingEvent>,
+    /// `FrameReady` events sent and not received yet
+    pending_frames: Arc<AtomicUsize>,
+}
+
+/// The events of a session, see `EventBus::subscribe`.
+/// Frames are dropped when it lags behind, the other events are always delivered
+pub struct Subscription {
+    rx: Receiver<StreamingEvent>,
+    pending_frames: Arc<AtomicUsize>,
+}
+
+impl Subscription {
+    fn received(&self, event: StreamingEvent) -> StreamingEvent {
+        if let StreamingEvent::FrameReady(_) = event {
+            self.pending_frames.fetch_sub(1, Ordering::Relaxed);
+        }
+        event
+    }
+
+    /// Waits for the next event, fails once the session is gone
+    pub fn recv(&self) -> Result<StreamingEvent, RecvError> {
+        self.rx.recv().map(|event| self.received(event))
+    }
+
+    pub fn try_recv(&self) -> Result<StreamingEvent, TryRecvError> {
+        self.rx.try_recv().map(|event| self.received(event))
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<StreamingEvent, RecvTimeoutError> {
+        self.rx
+            .recv_timeout(timeout)
+            .map(|event| self.received(event))
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = StreamingEvent;
+
+    fn next(&mut self) -> Option<StreamingEvent> {
+        self.recv().ok()
+    }
+}
+
+/// Broadcasts `StreamingEvent`s to every subscriber.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&self) -> Subscription {
+        let (tx, rx) = channel();
+        let pending_frames = Arc::new(AtomicUsize::new(0));
+        self.subscribers.lock().unwrap().push(Subscriber {
+            tx,
+            pending_frames: pending_frames.clone(),
+        });
+        Subscription { rx, pending_frames }
+    }
+
+    pub fn emit(&self, event: StreamingEvent) {
+        let is_frame = matches!(event, StreamingEvent::FrameReady(_));
+        // subscribers that dropped their receiver are forgotten
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            if is_frame {
+                if subscriber.pending_frames.load(Ordering::Relaxed) >= MAX_PENDING_FRAMES {
+                    return true;
+                }
+                subscriber.pending_frames.fetch_add(1, Ordering::Relaxed);
+            }
+            subscriber.tx.send(event.clone()).is_ok()
+        });
+    }
+}
+
+/// Counts the frames going through an appsink and computes the fps every second.
+pub(crate) struct StatsCounter {
+    frames: u64,
+    window_frames: u64,
+    window_start: Instant,
+}
+
+impl StatsCounter {
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    pub fn new() -> Self {
+        Self {
+            frames: 0,
+            window_frames: 0,
+            window_start: Instant::now(),
+        }
+    }
+
+    /// Registers a frame, returns the updated stats once per second
+    pub fn frame(&mut self) -> Option<StreamingStats> {
+        self.frames += 1;
+        self.window_frames += 1;
+
+        let elapsed = self.window_start.elapsed();
+        if elapsed < Self::WINDOW {
+            return None;
+        }
+
+        let fps = self.window_frames as f32 / elapsed.as_secs_f32();
+        self.window_frames = 0;
+        self.window_start = Instant::now();
+
+        Some(StreamingStats {
+            frames: self.frames,
+            fps,
+            receivers: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::frame::{FrameFormat, VideoFrame};
+
+    fn frame() -> StreamingEvent {
+        StreamingEvent::FrameReady(Arc::new(VideoFrame {
+            format: FrameFormat::Rgba,
+            width: 1,
+            height: 1,
+            stride: 4,
+            data: vec![0; 4],
+        }))
+    }
+
+    #[test]
+    fn lagging_subscriber_drops_frames_but_not_events() {
+        let bus = EventBus::default();
+        let subscription = bus.subscribe();
+        for _ in 0..10 {
+            bus.emit(frame());
+        }
+        bus.emit(StreamingEvent::Disconnected);
+
+        let events: Vec<_> = std::iter::from_fn(|| subscription.try_recv().ok()).collect();
+        assert_eq!(events.len(), MAX_PENDING_FRAMES + 1);
+        assert!(matches!(events.last(), Some(StreamingEvent::Disconnected)));
+
+        // once received, frames flow again
+        bus.emit(frame());
+        assert!(matches!(
+            subscription.try_recv(),
+            Ok(StreamingEvent::FrameReady(_))
+        ));
+    }
+}
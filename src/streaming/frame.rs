@@ -0,0 +1,186 @@
+use byte_slice_cast::*;
+use std::borrow::Cow;
+
+use gstreamer::{self as gst, element_error};
+use gstreamer_app as gst_app;
+use gstreamer_video as gst_video;
+use image::{ImageFormat, RgbaImage};
+
+/// Format of the frames delivered by `StreamingEvent::FrameReady`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrameFormat {
+    /// JPEG encoded frames, cheap to send around but must be decoded to be shown
+    #[default]
+    Jpeg,
+    /// Raw RGBA pixels, ready to be uploaded as a texture
+    Rgba,
+}
+
+impl FrameFormat {
+    /// Caps of the appsink delivering this format
+    pub(crate) fn caps(&self) -> gst::Caps {
+        match self {
+            FrameFormat::Jpeg => gst::Caps::builder("image/jpeg").build(),
+            FrameFormat::Rgba => gst_video::VideoCapsBuilder::new()
+                .format(gst_video::VideoFormat::Rgba)
+                .build(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VideoFrame {
+    pub format: FrameFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per row (including padding), only meaningful for `FrameFormat::Rgba`
+    pub stride: usize,
+    pub data: Vec<u8>,
+}
+
+impl VideoFrame {
+    /// RGBA pixels without row padding, as expected by most image libraries
+    pub fn packed_rgba(&self) -> Cow<'_, [u8]> {
+        let row = self.width as usize * 4;
+        if self.stride == row {
+            return Cow::Borrowed(&self.data);
+        }
+
+        Cow::Owned(
+            self.data
+                .chunks(self.stride)
+                .take(self.height as usize)
+                .flat_map(|line| &line[..row])
+                .copied()
+                .collect(),
+        )
+    }
+
+    /// Decodes the frame if needed, `None` if it's not valid
+    pub(crate) fn to_rgba_image(&self) -> Option<RgbaImage> {
+        match self.format {
+            FrameFormat::Jpeg => image::load_from_memory_with_format(&self.data, ImageFormat::Jpeg)
+                .ok()
+                .map(|image| image.to_rgba8()),
+            FrameFormat::Rgba => {
+                RgbaImage::from_raw(self.width, self.height, self.packed_rgba().into_owned())
+            }
+        }
+    }
+}
+
+/// Maximum time to convert a frame with `to_rgba_frame`
+const CONVERT_TIMEOUT_SECONDS: u64 = 2;
+
+/// Converts a raw video sample in any format to an RGBA `VideoFrame`, `None` if it can't be
+pub(crate) fn to_rgba_frame(sample: &gst::Sample) -> Option<VideoFrame> {
+    let rgba = gst_video::convert_sample(
+        sample,
+        &FrameFormat::Rgba.caps(),
+        gst::ClockTime::from_seconds(CONVERT_TIMEOUT_SECONDS),
+    )
+    .ok()?;
+    let info = gst_video::VideoInfo::from_caps(rgba.caps()?).ok()?;
+    let map = rgba.buffer()?.map_readable().ok()?;
+
+    Some(VideoFrame {
+        format: FrameFormat::Rgba,
+        width: info.width(),
+        height: info.height(),
+        stride: info.stride()[0] as usize,
+        data: map.as_slice().to_vec(),
+    })
+}
+
+/// Pulls the next sample from the appsink and copies it in a `VideoFrame`
+pub(crate) fn pull_frame(
+    appsink: &gst_app::AppSink,
+    format: FrameFormat,
+) -> Result<VideoFrame, gst::FlowError> {
+    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+    sample_to_frame(appsink, &sample, format)
+}
+
+/// Like `pull_frame`, for the frame shown while paused
+pub(crate) fn pull_preroll_frame(
+    appsink: &gst_app::AppSink,
+    format: FrameFormat,
+) -> Result<VideoFrame, gst::FlowError> {
+    let sample = appsink.pull_preroll().map_err(|_| gst::FlowError::Eos)?;
+    sample_to_frame(appsink, &sample, format)
+}
+
+fn sample_to_frame(
+    appsink: &gst_app::AppSink,
+    sample: &gst::Sample,
+    format: FrameFormat,
+) -> Result<VideoFrame, gst::FlowError> {
+    let buffer = sample.buffer().ok_or_else(|| {
+        element_error!(
+            appsink,
+            gst::ResourceError::Failed,
+            ("Failed to get buffer from appsink")
+        );
+
+        gst::FlowError::Error
+    })?;
+
+    let caps = sample.caps().ok_or_else(|| {
+        element_error!(
+            appsink,
+            gst::ResourceError::Failed,
+            ("Failed to get caps from appsink")
+        );
+
+        gst::FlowError::Error
+    })?;
+
+    let (width, height, stride) = match format {
+        FrameFormat::Jpeg => {
+            let structure = caps.structure(0).ok_or(gst::FlowError::NotNegotiated)?;
+            let width = structure.get::<i32>("width").unwrap_or(0);
+            let height = structure.get::<i32>("height").unwrap_or(0);
+            (width as u32, height as u32, 0)
+        }
+        FrameFormat::Rgba => {
+            let info = gst_video::VideoInfo::from_caps(caps).map_err(|_| {
+                element_error!(
+                    appsink,
+                    gst::ResourceError::Failed,
+                    ("Failed to parse video info from caps")
+                );
+
+                gst::FlowError::NotNegotiated
+            })?;
+            (info.width(), info.height(), info.stride()[0] as usize)
+        }
+    };
+
+    let map = buffer.map_readable().map_err(|_| {
+        element_error!(
+            appsink,
+            gst::ResourceError::Failed,
+            ("Failed to map buffer readable")
+        );
+
+        gst::FlowError::Error
+    })?;
+
+    let samples = map.as_slice_of::<u8>().map_err(|_| {
+        element_error!(
+            appsink,
+            gst::ResourceError::Failed,
+            ("Failed to interpret buffer as bytes")
+        );
+
+        gst::FlowError::Error
+    })?;
+
+    Ok(VideoFrame {
+        format,
+        width,
+        height,
+        stride,
+        data: samples.to_vec(),
+    })
+}
@@ -0,0 +1,849 @@
+use std::sync::Arc;
+
+use gstreamer::{self as gst, glib, prelude::*};
+use gstreamer_app as gst_app;
+use gstreamer_video as gst_video;
+use thiserror::Error;
+
+use super::blank::{BlankContent, BlankError, BlankSource};
+use super::frame::FrameFormat;
+use super::privacy::PRIVACY_FORMAT;
+use super::simulcast::SimulcastLayer;
+use super::source::{CaptureBranch, CaptureSource};
+use super::watermark::WatermarkSettings;
+
+/// Port where the receivers listen for the RTP stream
+pub(crate) const RTP_PORT: i32 = 9001;
+pub(crate) const FRAMERATE: i32 = 30;
+pub const MIN_FRAMERATE: u32 = 5;
+pub const MAX_FRAMERATE: u32 = 60;
+/// Longest gap the recording branch fills with copies of the last frame
+const RECORDING_MAX_DUPLICATION: gst::ClockTime = gst::ClockTime::SECOND;
+
+#[derive(Error, Debug)]
+pub enum BuildError {
+    #[error("Missing GStreamer element \"{0}\", is its plugin installed?")]
+    MissingElement(&'static str),
+
+    #[error("Failed to link {0} to {1}")]
+    Link(String, String),
+
+    #[error("Invalid capture source: {0}")]
+    InvalidSource(String),
+
+    #[error("Invalid blank screen: {0}")]
+    InvalidBlank(#[from] BlankError),
+
+    #[error("GStreamer error: {0}")]
+    GStreamer(#[from] glib::BoolError),
+}
+
+/// H264 encoders the caster can use, receivers only need an H264 decoder
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VideoEncoder {
+    /// Software encoder, available almost everywhere
+    #[default]
+    X264,
+    OpenH264,
+    /// NVIDIA hardware encoder
+    Nvenc,
+    /// VA-API hardware encoder (Intel/AMD on Linux)
+    Vaapi,
+}
+
+impl VideoEncoder {
+    pub const ALL: [VideoEncoder; 4] = [
+        VideoEncoder::X264,
+        VideoEncoder::OpenH264,
+        VideoEncoder::Nvenc,
+        VideoEncoder::Vaapi,
+    ];
+
+    pub fn element_name(&self) -> &'static str {
+        match self {
+            VideoEncoder::X264 => "x264enc",
+            VideoEncoder::OpenH264 => "openh264enc",
+            VideoEncoder::Nvenc => "nvh264enc",
+            VideoEncoder::Vaapi => "vaapih264enc",
+        }
+    }
+
+    /// Properties configuring the encoder for low latency
+    fn properties(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            VideoEncoder::X264 => &[("tune", "zerolatency")],
+            VideoEncoder::OpenH264 => &[("usage-type", "screen")],
+            VideoEncoder::Nvenc => &[("zerolatency", "true")],
+            VideoEncoder::Vaapi => &[],
+        }
+    }
+
+    fn set_bitrate(&self, encoder: &gst::Element, kbps: u32) {
+        match self {
+            VideoEncoder::OpenH264 => encoder.set_property("bitrate", kbps * 1000),
+            _ => encoder.set_property("bitrate", kbps),
+        }
+    }
+}
+
+impl std::fmt::Display for VideoEncoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            VideoEncoder::X264 => "x264",
+            VideoEncoder::OpenH264 => "OpenH264",
+            VideoEncoder::Nvenc => "NVENC",
+            VideoEncoder::Vaapi => "VA-API",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Size and framerate of the stream sent to the receivers, independent of the capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputSettings {
+    /// Height the stream is scaled to, keeping the aspect ratio. `None` keeps the captured size
+    pub height: Option<u32>,
+    /// Between `MIN_FRAMERATE` and `MAX_FRAMERATE`
+    pub framerate: u32,
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        Self {
+            height: None,
+            framerate: FRAMERATE as u32,
+        }
+    }
+}
+
+impl OutputSettings {
+    pub(crate) fn caps(&self) -> gst::Caps {
+        let framerate = self.framerate.clamp(MIN_FRAMERATE, MAX_FRAMERATE);
+        let mut caps = gst::Caps::builder("video/x-raw")
+            .field("framerate", gst::Fraction::new(framerate as i32, 1));
+        if let Some(height) = self.height {
+            // with a square pixel aspect ratio videoscale picks the width that keeps the proportions
+            caps = caps
+                .field("height", height as i32)
+                .field("pixel-aspect-ratio", gst::Fraction::new(1, 1));
+        }
+        caps.build()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+    pub frame_format: FrameFormat,
+    pub source: CaptureSource,
+    pub encoder: VideoEncoder,
+    pub output: OutputSettings,
+    /// Qualities sent in parallel, empty for a single stream following `output`
+    pub layers: Vec<SimulcastLayer>,
+    /// Layer given to the receivers when they join
+    pub default_layer: usize,
+    pub watermarks: WatermarkSettings,
+    /// Shown by `StreamingServer::blank_screen`, a frozen frame is black until there is a frame
+    pub blank: BlankContent,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    pub frame_format: FrameFormat,
+}
+
+/// Handles to the elements of the caster pipeline that are controlled at runtime
+pub(crate) struct ServerPipeline {
+    pub pipeline: gst::Pipeline,
+    /// The screen capture element, if capturing the screen
+    pub screen: Option<gst::Element>,
+    /// Crops the captured screen where the source can't do it by itself
+    pub crop: Option<gst::Element>,
+    pub selector: gst::Element,
+    /// Selector pad of the captured screen
+    pub capture_pad: gst::Pad,
+    /// Selector pad of the blank screen
+    pub blank_pad: gst::Pad,
+    pub blank: Arc<BlankSource>,
+    /// Capsfilter whose output gets the privacy masks
+    pub masking: gst::Element,
+    /// `overlaycomposition` drawing the annotations on the selected input
+    pub annotation: gst::Element,
+    /// One per simulcast layer, or a single one
+    pub network: Vec<NetworkBranch>,
+    /// Encoder of the recordings that can't reuse `network[0]`, closed while not recording
+    pub recording: RecordingBranch,
+    pub appsink: gst_app::AppSink,
+}
+
+/// Raw video in, H264 out
+pub(crate) struct EncoderBranch {
+    /// Gives the stream the framerate of `caps`
+    pub rate: gst::Element,
+    /// Caps of the stream, see `OutputSettings`
+    pub caps: gst::Element,
+    /// Tee of the encoded H264, where recordings are attached
+    pub encoded: gst::Element,
+}
+
+/// An encoder branch sending the stream to the receivers
+pub(crate) struct NetworkBranch {
+    pub encoder: EncoderBranch,
+    /// Height of the simulcast layer, that doesn't follow `OutputSettings::height`
+    pub height: Option<u32>,
+    pub multiudpsink: gst::Element,
+}
+
+/// An encoder branch that only runs while recording, for the recordings that must differ from
+/// the network stream: at the captured size while it's scaled, or stamped by watermarks that are
+/// for the recordings only
+pub(crate) struct RecordingBranch {
+    /// Drops the raw frames while not recording, so that the encoder is idle
+    pub valve: gst::Element,
+    pub encoder: EncoderBranch,
+}
+
+impl NetworkBranch {
+    /// Gives the stream `output`, except for the height of a simulcast layer
+    pub fn apply_output(&self, output: OutputSettings) {
+        let output = OutputSettings {
+            height: self.height.or(output.height),
+            ..output
+        };
+        self.encoder.caps.set_property("caps", output.caps());
+    }
+}
+
+/// Plays a recording, see `build_player`
+pub(crate) struct PlayerPipeline {
+    pub pipeline: gst::Pipeline,
+    pub appsink: gst_app::AppSink,
+}
+
+/// Handles to the elements of the receiver pipeline that are controlled at runtime
+pub(crate) struct ClientPipeline {
+    pub pipeline: gst::Pipeline,
+    /// Tee of the received H264, where recordings are attached
+    pub tee: gst::Element,
+    pub appsink: gst_app::AppSink,
+}
+
+pub(crate) fn make(factory: &'static str) -> Result<gst::Element, BuildError> {
+    gst::ElementFactory::make(factory)
+        .build()
+        .map_err(|_| BuildError::MissingElement(factory))
+}
+
+pub(crate) fn link_pads(src: &gst::Pad, sink: &gst::Pad) -> Result<(), BuildError> {
+    src.link(sink).map(|_| ()).map_err(|_| {
+        BuildError::Link(
+            src.path_string().to_string(),
+            sink.path_string().to_string(),
+        )
+    })
+}
+
+pub(crate) fn framerate_caps() -> gst::Caps {
+    gst::Caps::builder("video/x-raw")
+        .field("framerate", gst::Fraction::new(FRAMERATE, 1))
+        .build()
+}
+
+pub(crate) fn capsfilter(caps: &gst::Caps) -> Result<gst::Element, BuildError> {
+    let filter = make("capsfilter")?;
+    filter.set_property("caps", caps);
+    Ok(filter)
+}
+
+/// Adds the elements to the pipeline and links them in order
+pub(crate) fn add_chain(
+    pipeline: &gst::Pipeline,
+    elements: &[gst::Element],
+) -> Result<(), BuildError> {
+    pipeline.add_many(elements)?;
+    gst::Element::link_many(elements)?;
+    Ok(())
+}
+
+/// `queue ! videoconvert ! [jpegenc !] appsink`, the branch feeding `StreamingEvent::FrameReady`
+fn preview_branch(
+    pipeline: &gst::Pipeline,
+    frame_format: FrameFormat,
+) -> Result<(gst::Element, gst_app::AppSink), BuildError> {
+    let queue = make("queue")?;
+    let convert = make("videoconvert")?;
+    let appsink = gst_app::AppSink::builder()
+        .max_buffers(1)
+        .caps(&frame_format.caps())
+        .build();
+
+    let mut elements = vec![queue.clone(), convert];
+    if frame_format == FrameFormat::Jpeg {
+        elements.push(make("jpegenc")?);
+    }
+    elements.push(appsink.clone().upcast());
+
+    add_chain(pipeline, &elements)?;
+
+    Ok((queue, appsink))
+}
+
+/// Builds
+/// ```text
+/// capture source ! selector.sink_0
+/// appsrc ! videoconvert ! selector.sink_1
+/// selector ! videoconvert ! capsfilter ! overlaycomposition ! tee ! network branch, one per simulcast layer
+///                                                             tee ! valve ! encoder branch, for the recordings
+///                                                             tee ! preview
+/// ```
+pub(crate) fn build_server(config: &ServerConfig) -> Result<ServerPipeline, BuildError> {
+    let pipeline = gst::Pipeline::new();
+
+    // capture
+    let CaptureBranch {
+        screen,
+        crop,
+        output,
+    } = config.source.build(&pipeline)?;
+
+    // blank screen
+    // no frame to freeze yet
+    let blank_content = match &config.blank {
+        BlankContent::FrozenFrame => BlankContent::Color([0, 0, 0]),
+        content => content.clone(),
+    };
+    let blank = BlankSource::new(blank_content)?;
+    let blank_convert = make("videoconvert")?;
+    add_chain(&pipeline, &[blank.element(), blank_convert.clone()])?;
+
+    let selector = make("input-selector")?;
+    let masking_convert = make("videoconvert")?;
+    let masking = capsfilter(
+        &gst_video::VideoCapsBuilder::new()
+            .format(PRIVACY_FORMAT)
+            .build(),
+    )?;
+    let annotation = make("overlaycomposition")?;
+    let tee = make("tee")?;
+    add_chain(
+        &pipeline,
+        &[
+            selector.clone(),
+            masking_convert,
+            masking.clone(),
+            annotation.clone(),
+            tee.clone(),
+        ],
+    )?;
+
+    let capture_pad = selector.request_pad_simple("sink_%u").unwrap();
+    let blank_pad = selector.request_pad_simple("sink_%u").unwrap();
+    link_pads(&output.static_pad("src").unwrap(), &capture_pad)?;
+    link_pads(&blank_convert.static_pad("src").unwrap(), &blank_pad)?;
+    selector.set_property("active-pad", &capture_pad);
+
+    // network
+    let network = if config.layers.is_empty() {
+        vec![network_branch(&pipeline, &tee, config, None)?]
+    } else {
+        config
+            .layers
+            .iter()
+            .map(|layer| network_branch(&pipeline, &tee, config, Some(layer)))
+            .collect::<Result<_, _>>()?
+    };
+
+    // recording, at the captured size whatever the output and always watermarked
+    let valve = make("valve")?;
+    valve.set_property("drop", true);
+    pipeline.add(&valve)?;
+    tee.link(&valve)?;
+    let encoder = encoder_branch(
+        &pipeline,
+        &valve,
+        config.encoder,
+        OutputSettings::default(),
+        None,
+        config.watermarks.elements()?,
+    )?;
+    // nothing is linked until a recording starts
+    encoder.encoded.set_property("allow-not-linked", true);
+    // the frames dropped by the valve must not come back as a burst of copies when it opens
+    encoder.rate.set_property("skip-to-first", true);
+    encoder
+        .rate
+        .set_property("max-duplication-time", RECORDING_MAX_DUPLICATION.nseconds());
+    let recording = RecordingBranch { valve, encoder };
+
+    // preview
+    let (preview, appsink) = preview_branch(&pipeline, config.frame_format)?;
+    tee.link(&preview)?;
+
+    Ok(ServerPipeline {
+        pipeline,
+        screen,
+        crop,
+        selector,
+        capture_pad,
+        blank_pad,
+        blank,
+        masking,
+        annotation,
+        network,
+        recording,
+        appsink,
+    })
+}
+
+/// `upstream ! queue ! videorate ! videoscale ! capsfilter ! [watermarks !] videoconvert ! encoder ! tee`
+fn encoder_branch(
+    pipeline: &gst::Pipeline,
+    upstream: &gst::Element,
+    encoder: VideoEncoder,
+    output: OutputSettings,
+    bitrate: Option<u32>,
+    watermarks: Vec<gst::Element>,
+) -> Result<EncoderBranch, BuildError> {
+    let queue = make("queue")?;
+    let rate = make("videorate")?;
+    let scale = make("videoscale")?;
+    let caps = capsfilter(&output.caps())?;
+    let convert = make("videoconvert")?;
+    let encoder_element = make(encoder.element_name())?;
+    for (name, value) in encoder.properties() {
+        encoder_element.set_property_from_str(name, value);
+    }
+    if let Some(bitrate) = bitrate {
+        encoder.set_bitrate(&encoder_element, bitrate);
+    }
+    let encoded = make("tee")?;
+
+    let mut elements = vec![queue.clone(), rate.clone(), scale, caps.clone()];
+    // after scaling, so that they have the same size whatever the capture
+    elements.extend(watermarks);
+    elements.extend([convert, encoder_element, encoded.clone()]);
+    add_chain(pipeline, &elements)?;
+    upstream.link(&queue)?;
+
+    Ok(EncoderBranch {
+        rate,
+        caps,
+        encoded,
+    })
+}
+
+/// encoder branch `! queue ! rtph264pay ! multiudpsink`
+fn network_branch(
+    pipeline: &gst::Pipeline,
+    tee: &gst::Element,
+    config: &ServerConfig,
+    layer: Option<&SimulcastLayer>,
+) -> Result<NetworkBranch, BuildError> {
+    let height = layer.map(|layer| layer.height);
+    let output = OutputSettings {
+        height: height.or(config.output.height),
+        ..config.output
+    };
+
+    let watermarks = if config.watermarks.recordings_only {
+        Vec::new()
+    } else {
+        config.watermarks.elements()?
+    };
+    let encoder = encoder_branch(
+        pipeline,
+        tee,
+        config.encoder,
+        output,
+        layer.map(|layer| layer.bitrate),
+        watermarks,
+    )?;
+
+    let send_queue = make("queue")?;
+    let payloader = make("rtph264pay")?;
+    // resend SPS/PPS with every keyframe, the output size can change while casting
+    payloader.set_property("config-interval", -1i32);
+    let multiudpsink = make("multiudpsink")?;
+    add_chain(
+        pipeline,
+        &[send_queue.clone(), payloader, multiudpsink.clone()],
+    )?;
+    encoder.encoded.link(&send_queue)?;
+
+    Ok(NetworkBranch {
+        encoder,
+        height,
+        multiudpsink,
+    })
+}
+
+/// Builds
+/// ```text
+/// udpsrc ! rtph264depay ! tee ! queue ! decodebin ~ preview
+/// ```
+pub(crate) fn build_client(config: &ClientConfig) -> Result<ClientPipeline, BuildError> {
+    let pipeline = gst::Pipeline::new();
+
+    let udpsrc = make("udpsrc")?;
+    udpsrc.set_property("port", RTP_PORT);
+    udpsrc.set_property(
+        "caps",
+        gst::Caps::builder("application/x-rtp")
+            .field("media", "video")
+            .field("clock-rate", 90000i32)
+            .field("encoding-name", "H264")
+            .field("payload", 96i32)
+            .build(),
+    );
+    let depay = make("rtph264depay")?;
+    let tee = make("tee")?;
+    let queue = make("queue")?;
+    let decodebin = make("decodebin")?;
+    pipeline.add_many([&udpsrc, &depay, &tee, &queue, &decodebin])?;
+    gst::Element::link_many([&udpsrc, &depay, &tee, &queue, &decodebin])?;
+
+    // decodebin exposes its pad only once it knows the stream
+    let (preview, appsink) = preview_branch(&pipeline, config.frame_format)?;
+    let preview_sink = preview.static_pad("sink").unwrap();
+    decodebin.connect_pad_added(move |_, pad| {
+        if preview_sink.is_linked() {
+            return;
+        }
+        if let Err(e) = link_pads(pad, &preview_sink) {
+            println!("{}", e);
+        }
+    });
+
+    Ok(ClientPipeline {
+        pipeline,
+        tee,
+        appsink,
+    })
+}
+
+/// Builds
+/// ```text
+/// uridecodebin ~ preview
+/// ```
+pub(crate) fn build_player(
+    uri: &str,
+    frame_format: FrameFormat,
+) -> Result<PlayerPipeline, BuildError> {
+    let pipeline = gst::Pipeline::new();
+
+    let decodebin = make("uridecodebin")?;
+    decodebin.set_property("uri", uri);
+    pipeline.add(&decodebin)?;
+
+    let (preview, appsink) = preview_branch(&pipeline, frame_format)?;
+    let preview_sink = preview.static_pad("sink").unwrap();
+    // recordings may have more streams, only the video one is shown
+    decodebin.connect_pad_added(move |_, pad| {
+        let is_video = pad
+            .current_caps()
+            .and_then(|caps| caps.structure(0).map(|s| s.name().starts_with("video/")))
+            .unwrap_or(false);
+        if !is_video || preview_sink.is_linked() {
+            return;
+        }
+        if let Err(e) = link_pads(pad, &preview_sink) {
+            println!("{}", e);
+        }
+    });
+
+    Ok(PlayerPipeline { pipeline, appsink })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::watermark::{Watermark, WatermarkContent};
+
+    /// Builds the caster pipeline, `None` if an element it needs is not installed
+    fn try_build_server(config: ServerConfig) -> Option<ServerPipeline> {
+        gst::init().unwrap();
+        match build_server(&config) {
+            Ok(server) => Some(server),
+            Err(BuildError::MissingElement(element)) => {
+                println!("skipped, {} is not installed", element);
+                None
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Captures a test pattern, that needs neither a display nor a camera
+    fn config() -> ServerConfig {
+        ServerConfig {
+            source: CaptureSource::TestPattern("smpte".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn factory(element: &gst::Element) -> String {
+        element
+            .factory()
+            .map(|f| f.name().to_string())
+            .unwrap_or_default()
+    }
+
+    /// The elements fed by `element`, one per linked src pad
+    fn downstream(element: &gst::Element) -> Vec<gst::Element> {
+        element
+            .src_pads()
+            .iter()
+            .filter_map(|pad| pad.peer())
+            .filter_map(|peer| peer.parent_element())
+            .collect()
+    }
+
+    /// Factories of the elements linked before `element`, nearest first, up to one
+    /// without a single sink pad such as the input-selector
+    fn upstream(element: &gst::Element) -> Vec<String> {
+        let mut chain = Vec::new();
+        let mut element = element.clone();
+        while let Some(peer) = element
+            .static_pad("sink")
+            .and_then(|pad| pad.peer())
+            .and_then(|peer| peer.parent_element())
+        {
+            chain.push(factory(&peer));
+            element = peer;
+        }
+        chain
+    }
+
+    /// Fails unless `expected` appears in `chain` in this order, other elements may sit in between
+    fn assert_linked_in_order(chain: &[String], expected: &[&str]) {
+        let mut rest = chain.iter();
+        for name in expected {
+            assert!(
+                rest.any(|element| element == name),
+                "{} missing or out of order in {:?}",
+                name,
+                chain
+            );
+        }
+    }
+
+    #[test]
+    fn capture_and_blank_screen_feed_the_selector() {
+        let Some(server) = try_build_server(config()) else {
+            return;
+        };
+
+        assert!(server.capture_pad.is_linked());
+        assert!(server.blank_pad.is_linked());
+        assert_eq!(
+            server.selector.property::<gst::Pad>("active-pad"),
+            server.capture_pad
+        );
+    }
+
+    #[test]
+    fn network_branch_encodes_the_selected_input() {
+        let Some(server) = try_build_server(config()) else {
+            return;
+        };
+
+        assert_linked_in_order(
+            &upstream(&server.network[0].multiudpsink),
+            &[
+                "rtph264pay",
+                VideoEncoder::default().element_name(),
+                "videoconvert",
+                "queue",
+                "tee",
+                "input-selector",
+            ],
+        );
+    }
+
+    #[test]
+    fn every_installed_encoder_is_linked() {
+        gst::init().unwrap();
+        for encoder in VideoEncoder::ALL {
+            if gst::ElementFactory::find(encoder.element_name()).is_none() {
+                continue;
+            }
+            let Some(server) = try_build_server(ServerConfig {
+                encoder,
+                ..config()
+            }) else {
+                return;
+            };
+
+            assert_linked_in_order(
+                &upstream(&server.network[0].multiudpsink),
+                &["rtph264pay", encoder.element_name()],
+            );
+        }
+    }
+
+    #[test]
+    fn preview_shares_the_selected_input() {
+        for frame_format in [FrameFormat::Jpeg, FrameFormat::Rgba] {
+            let Some(server) = try_build_server(ServerConfig {
+                frame_format,
+                ..config()
+            }) else {
+                return;
+            };
+
+            let chain = upstream(server.appsink.upcast_ref());
+            assert_eq!(
+                chain.contains(&"jpegenc".to_string()),
+                frame_format == FrameFormat::Jpeg
+            );
+            assert_linked_in_order(&chain, &["videoconvert", "queue", "tee", "input-selector"]);
+        }
+    }
+
+    #[test]
+    fn slideshow_feeds_the_selector() {
+        let path = std::env::temp_dir().join("pipeline_test_slide.png");
+        image::RgbaImage::new(64, 48).save(&path).unwrap();
+        let server = try_build_server(ServerConfig {
+            source: CaptureSource::Slideshow {
+                images: vec![path.clone()],
+                interval: std::time::Duration::from_secs(1),
+            },
+            ..config()
+        });
+        let _ = std::fs::remove_file(&path);
+        let Some(server) = server else {
+            return;
+        };
+
+        assert!(server.capture_pad.is_linked());
+        let capture = upstream(
+            &server
+                .capture_pad
+                .peer()
+                .and_then(|pad| pad.parent_element())
+                .unwrap(),
+        );
+        assert_linked_in_order(&capture, &["videoscale", "videoconvert", "appsrc"]);
+    }
+
+    #[test]
+    fn simulcast_layers_get_a_branch_each() {
+        let Some(server) = try_build_server(ServerConfig {
+            output: OutputSettings {
+                height: Some(480),
+                ..Default::default()
+            },
+            layers: SimulcastLayer::DEFAULTS.to_vec(),
+            ..config()
+        }) else {
+            return;
+        };
+
+        assert_eq!(server.network.len(), SimulcastLayer::DEFAULTS.len());
+        for (branch, layer) in server.network.iter().zip(SimulcastLayer::DEFAULTS) {
+            // the layer height wins over the output one
+            assert_eq!(branch.height, Some(layer.height));
+            let caps = branch.encoder.caps.property::<gst::Caps>("caps");
+            let height = caps.structure(0).unwrap().get::<i32>("height").ok();
+            assert_eq!(height, Some(layer.height as i32));
+            assert_linked_in_order(
+                &upstream(&branch.multiudpsink),
+                &["rtph264pay", "capsfilter", "tee", "input-selector"],
+            );
+        }
+    }
+
+    #[test]
+    fn watermarks_are_linked_where_they_are_wanted() {
+        for recordings_only in [false, true] {
+            let Some(server) = try_build_server(ServerConfig {
+                watermarks: WatermarkSettings {
+                    watermarks: vec![Watermark::new(WatermarkContent::Text("test".to_string()))],
+                    recordings_only,
+                },
+                ..config()
+            }) else {
+                return;
+            };
+
+            let network = upstream(&server.network[0].multiudpsink);
+            assert_eq!(
+                network.contains(&"textoverlay".to_string()),
+                !recordings_only
+            );
+            // recordings are always stamped, after scaling
+            assert_linked_in_order(
+                &upstream(&server.recording.encoder.encoded),
+                &["textoverlay", "capsfilter", "videoscale", "valve", "tee"],
+            );
+        }
+    }
+
+    #[test]
+    fn recording_keeps_the_captured_size() {
+        let Some(server) = try_build_server(ServerConfig {
+            output: OutputSettings {
+                height: Some(480),
+                ..Default::default()
+            },
+            ..config()
+        }) else {
+            return;
+        };
+
+        let height = |filter: &gst::Element| {
+            let caps = filter.property::<gst::Caps>("caps");
+            caps.structure(0).unwrap().get::<i32>("height").ok()
+        };
+        assert_eq!(height(&server.network[0].encoder.caps), Some(480));
+        assert_eq!(height(&server.recording.encoder.caps), None);
+
+        // idle until a recording starts, without a burst of copies when it does
+        assert!(server.recording.valve.property::<bool>("drop"));
+        let rate = &server.recording.encoder.rate;
+        assert!(rate.property::<bool>("skip-to-first"));
+        assert_eq!(
+            rate.property::<u64>("max-duplication-time"),
+            RECORDING_MAX_DUPLICATION.nseconds()
+        );
+        assert_linked_in_order(
+            &upstream(&server.recording.encoder.encoded),
+            &["videorate", "queue", "valve", "tee"],
+        );
+    }
+
+    #[test]
+    fn client_depayloads_then_decodes() {
+        gst::init().unwrap();
+        let client = match build_client(&ClientConfig {
+            frame_format: FrameFormat::Rgba,
+        }) {
+            Ok(client) => client,
+            Err(BuildError::MissingElement(element)) => {
+                println!("skipped, {} is not installed", element);
+                return;
+            }
+            Err(e) => panic!("{}", e),
+        };
+
+        let udpsrc = client
+            .pipeline
+            .iterate_elements()
+            .into_iter()
+            .filter_map(Result::ok)
+            .find(|element| factory(element) == "udpsrc")
+            .unwrap();
+        let depay = &downstream(&udpsrc)[0];
+        assert_eq!(factory(depay), "rtph264depay");
+        let tee = &downstream(depay)[0];
+        let queue = &downstream(tee)[0];
+        assert_eq!(
+            downstream(queue).iter().map(factory).collect::<Vec<_>>(),
+            ["decodebin"]
+        );
+        // the preview is linked by decodebin once it knows the stream
+        assert_eq!(
+            upstream(client.appsink.upcast_ref()),
+            ["videoconvert", "queue"]
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use gstreamer::{self as gst, glib, prelude::*};
+use gstreamer_app as gst_app;
+use gstreamer_pbutils as gst_pbutils;
+use thiserror::Error;
+
+use super::bus;
+use super::event::{EventBus, StreamingEvent, Subscription};
+use super::frame::{pull_frame, pull_preroll_frame, FrameFormat};
+use super::pipeline::{self, BuildError, PlayerPipeline};
+
+/// Files shown in the list of recordings
+const RECORDING_EXTENSIONS: [&str; 3] = ["mp4", "mkv", "ts"];
+/// Maximum time to read the duration of a recording
+const DISCOVER_TIMEOUT_SECONDS: u64 = 5;
+
+#[derive(Error, Debug)]
+pub enum PlayerError {
+    #[error("GStreamer init error: {0}")]
+    GStreamerInitError(#[from] glib::Error),
+
+    #[error("Pipeline build error: {0}")]
+    PipelineBuildError(#[from] BuildError),
+
+    #[error("GStreamer state change error: {0}")]
+    GStreamerStateChangeError(#[from] gst::StateChangeError),
+
+    #[error("Seek failed: {0}")]
+    SeekError(#[from] glib::BoolError),
+
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// A saved recording, as listed by `list_recordings`
+#[derive(Debug, Clone)]
+pub struct RecordingInfo {
+    pub path: PathBuf,
+    /// In bytes
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// `None` until found by `discover_durations`, or if the file can't be read,
+    /// e.g. it's still being recorded
+    pub duration: Option<Duration>,
+}
+
+/// The recordings saved in `directory`, newest first, without their duration
+pub fn list_recordings(directory: &Path) -> Result<Vec<RecordingInfo>, PlayerError> {
+    let mut recordings: Vec<RecordingInfo> = std::fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| RECORDING_EXTENSIONS.contains(&ext))
+        })
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some(RecordingInfo {
+                path: entry.path(),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+                duration: None,
+            })
+        })
+        .collect();
+    recordings.sort_by(|a, b| b.modified.cmp(&a.modified));
+    Ok(recordings)
+}
+
+/// Reads the duration of the recordings one after the other in another thread, reading a
+/// file can take up to `DISCOVER_TIMEOUT_SECONDS`. `on_duration` is called from that thread
+/// for every file whose duration is known
+pub fn discover_durations(
+    paths: Vec<PathBuf>,
+    on_duration: impl Fn(&Path, Duration) + Send + 'static,
+) -> Result<(), PlayerError> {
+    gst::init()?;
+    let discoverer =
+        gst_pbutils::Discoverer::new(gst::ClockTime::from_seconds(DISCOVER_TIMEOUT_SECONDS))?;
+
+    thread::spawn(move || {
+        for path in paths {
+            let duration = glib::filename_to_uri(&path, None)
+                .ok()
+                .and_then(|uri| discoverer.discover_uri(&uri).ok())
+                .and_then(|info| info.duration());
+            if let Some(duration) = duration {
+                on_duration(&path, Duration::from_nanos(duration.nseconds()));
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Plays a recording, delivering its frames as `StreamingEvent::FrameReady` like a stream
+pub struct Player {
+    pipeline: gst::Pipeline,
+    ended: Arc<AtomicBool>,
+    events: EventBus,
+}
+
+impl Player {
+    /// Opens the recording, paused on its first frame
+    pub fn new(path: &Path, frame_format: FrameFormat) -> Result<Self, PlayerError> {
+        gst::init()?;
+
+        let uri = glib::filename_to_uri(path, None)?;
+        let PlayerPipeline { pipeline, appsink } = pipeline::build_player(&uri, frame_format)?;
+
+        let events = EventBus::default();
+        bus::watch(&pipeline, events.clone());
+
+        let ended = Arc::new(AtomicBool::new(false));
+        let ended_clone = ended.clone();
+        let events_clone = events.clone();
+        let events_clone2 = events.clone();
+        appsink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample(move |appsink| {
+                    let frame = pull_frame(appsink, frame_format)?;
+                    events_clone.emit(StreamingEvent::FrameReady(Arc::new(frame)));
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                // the frame shown while paused, after a seek or a step
+                .new_preroll(move |appsink| {
+                    let frame = pull_preroll_frame(appsink, frame_format)?;
+                    events_clone2.emit(StreamingEvent::FrameReady(Arc::new(frame)));
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                .eos(move |_| ended_clone.store(true, Ordering::Relaxed))
+                .build(),
+        );
+
+        pipeline.set_state(gst::State::Paused)?;
+
+        Ok(Self {
+            pipeline,
+            ended,
+            events,
+        })
+    }
+
+    pub fn subscribe(&self) -> Subscription {
+        self.events.subscribe()
+    }
+
+    /// Plays from the current position, or from the start if the end was reached
+    pub fn play(&self) -> Result<(), PlayerError> {
+        if self.is_ended() {
+            self.seek(Duration::ZERO)?;
+        }
+        self.pipeline.set_state(gst::State::Playing)?;
+        Ok(())
+    }
+
+    pub fn pause(&self) -> Result<(), PlayerError> {
+        self.pipeline.set_state(gst::State::Paused)?;
+        Ok(())
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.pipeline.current_state() == gst::State::Playing && !self.is_ended()
+    }
+
+    pub fn is_ended(&self) -> bool {
+        self.ended.load(Ordering::Relaxed)
+    }
+
+    pub fn seek(&self, position: Duration) -> Result<(), PlayerError> {
+        self.pipeline.seek_simple(
+            gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE,
+            gst::ClockTime::from_nseconds(position.as_nanos() as u64),
+        )?;
+        self.ended.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Pauses and shows the next frame
+    pub fn step_frame(&self) -> Result<(), PlayerError> {
+        self.pause()?;
+        self.pipeline.send_event(gst::event::Step::new(
+            gst::format::Buffers::ONE,
+            1.0,
+            true,
+            false,
+        ));
+        Ok(())
+    }
+
+    pub fn position(&self) -> Option<Duration> {
+        self.pipeline
+            .query_position::<gst::ClockTime>()
+            .map(|t| Duration::from_nanos(t.nseconds()))
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.pipeline
+            .query_duration::<gst::ClockTime>()
+            .map(|t| Duration::from_nanos(t.nseconds()))
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+    }
+}
@@ -0,0 +1,180 @@
+use ashpd::desktop::screencast::{CursorMode, PersistMode, Screencast, SourceType};
+use ashpd::WindowIdentifier;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PortalError {
+    #[error("Screen cast portal error: {0}")]
+    Portal(#[from] ashpd::Error),
+
+    #[error("No stream was selected")]
+    NoStream,
+
+    #[error("The portal thread ended unexpectedly")]
+    Closed,
+}
+
+/// Keeps the portal session open, the session is closed when this is dropped
+#[derive(Debug)]
+struct SessionGuard(Mutex<Sender<()>>);
+
+/// A PipeWire video node to capture with `CaptureSource::PipeWire`
+#[derive(Debug, Clone)]
+pub struct PipeWireStream {
+    /// Connection to the PipeWire remote given by the portal, `None` for the default daemon
+    pub fd: Option<Arc<OwnedFd>>,
+    pub node_id: u32,
+    _session: Option<Arc<SessionGuard>>,
+}
+
+impl PipeWireStream {
+    /// A node of the default PipeWire daemon, without going through the portal
+    pub fn from_node(node_id: u32) -> Self {
+        Self {
+            fd: None,
+            node_id,
+            _session: None,
+        }
+    }
+}
+
+impl PartialEq for PipeWireStream {
+    fn eq(&self, other: &Self) -> bool {
+        self.node_id == other.node_id
+            && self.fd.as_ref().map(|fd| fd.as_raw_fd())
+                == other.fd.as_ref().map(|fd| fd.as_raw_fd())
+    }
+}
+
+/// Something able to hand out a PipeWire stream of the screen
+pub trait ScreenCastPortal {
+    fn open(&self) -> Result<PipeWireStream, PortalError>;
+}
+
+/// The xdg-desktop-portal ScreenCast interface, the user chooses what to share
+pub struct DesktopPortal;
+
+impl ScreenCastPortal for DesktopPortal {
+    fn open(&self) -> Result<PipeWireStream, PortalError> {
+        let (result_tx, result_rx) = channel();
+        let (close_tx, close_rx) = channel::<()>();
+
+        // the session must outlive this call, so it's owned by a dedicated thread
+        thread::spawn(move || {
+            pollster::block_on(async move {
+                let proxy = match Screencast::new().await {
+                    Ok(proxy) => proxy,
+                    Err(e) => {
+                        let _ = result_tx.send(Err(e.into()));
+                        return;
+                    }
+                };
+
+                let result = async {
+                    let session = proxy.create_session().await?;
+                    proxy
+                        .select_sources(
+                            &session,
+                            CursorMode::Embedded,
+                            SourceType::Monitor | SourceType::Window,
+                            false,
+                            None,
+                            PersistMode::DoNot,
+                        )
+                        .await?;
+                    let response = proxy
+                        .start(&session, &WindowIdentifier::default())
+                        .await?
+                        .response()?;
+                    let node_id = response
+                        .streams()
+                        .first()
+                        .ok_or(PortalError::NoStream)?
+                        .pipe_wire_node_id();
+                    let fd = proxy.open_pipe_wire_remote(&session).await?;
+                    Ok::<_, PortalError>((session, node_id, fd))
+                }
+                .await;
+
+                match result {
+                    Ok((session, node_id, fd)) => {
+                        let _ = result_tx.send(Ok((node_id, fd)));
+                        // returns when the guard is dropped
+                        let _ = close_rx.recv();
+                        let _ = session.close().await;
+                    }
+                    Err(e) => {
+                        let _ = result_tx.send(Err(e));
+                    }
+                }
+            });
+        });
+
+        let (node_id, fd) = result_rx.recv().map_err(|_| PortalError::Closed)??;
+
+        Ok(PipeWireStream {
+            fd: Some(Arc::new(fd)),
+            node_id,
+            _session: Some(Arc::new(SessionGuard(Mutex::new(close_tx)))),
+        })
+    }
+}
+
+/// Skips the portal and hands out a fixed node of the default PipeWire daemon,
+/// e.g. the one of `gst-launch-1.0 videotestsrc ! pipewiresink`
+pub struct MockPortal {
+    pub node_id: u32,
+}
+
+impl ScreenCastPortal for MockPortal {
+    fn open(&self) -> Result<PipeWireStream, PortalError> {
+        Ok(PipeWireStream::from_node(self.node_id))
+    }
+}
+
+/// True when running in a Wayland session, where X11 capture doesn't work
+pub fn is_wayland_session() -> bool {
+    std::env::var_os("WAYLAND_DISPLAY").is_some()
+        || std::env::var("XDG_SESSION_TYPE").is_ok_and(|t| t == "wayland")
+}
+
+#[cfg(test)]
+mod tests {
+    use gstreamer::{self as gst, prelude::*};
+
+    use super::*;
+    use crate::streaming::source::CaptureSource;
+
+    #[test]
+    fn mock_portal_hands_out_its_node() {
+        let stream = MockPortal { node_id: 42 }.open().unwrap();
+        assert_eq!(stream.node_id, 42);
+        assert!(stream.fd.is_none());
+        assert_eq!(stream, PipeWireStream::from_node(42));
+    }
+
+    #[test]
+    fn mock_stream_is_captured_from_the_default_daemon() {
+        gst::init().unwrap();
+        if gst::ElementFactory::find("pipewiresrc").is_none() {
+            return;
+        }
+
+        let stream = MockPortal { node_id: 42 }.open().unwrap();
+        let pipeline = gst::Pipeline::new();
+        CaptureSource::PipeWire(stream).build(&pipeline).unwrap();
+
+        let source = pipeline
+            .iterate_elements()
+            .into_iter()
+            .filter_map(Result::ok)
+            .find(|e| e.factory().is_some_and(|f| f.name() == "pipewiresrc"))
+            .unwrap();
+        assert_eq!(source.property::<String>("path"), "42");
+        assert_eq!(source.property::<i32>("fd"), -1);
+    }
+}
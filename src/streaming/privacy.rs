@@ -0,0 +1,359 @@
+use std::sync::{Arc, Mutex};
+
+use gstreamer::{self as gst, prelude::*};
+use gstreamer_video as gst_video;
+
+use super::annotation::Point;
+
+/// Pixelation blocks and blur radius are this fraction of the frame height
+const PIXELATE_DIVISOR: usize = 50;
+const BLUR_DIVISOR: usize = 60;
+/// Box blurs in a row, three are close enough to a gaussian blur
+const BLUR_PASSES: usize = 3;
+/// Bytes per pixel of the masked frames, see `PRIVACY_FORMAT`
+const PIXEL_SIZE: usize = 4;
+/// Format the frames are converted to before masking
+pub(crate) const PRIVACY_FORMAT: gst_video::VideoFormat = gst_video::VideoFormat::Bgrx;
+
+/// How a masked region is hidden
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MaskStyle {
+    Blur,
+    #[default]
+    Pixelate,
+    Black,
+}
+
+impl MaskStyle {
+    pub const ALL: [MaskStyle; 3] = [MaskStyle::Blur, MaskStyle::Pixelate, MaskStyle::Black];
+}
+
+impl std::fmt::Display for MaskStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            MaskStyle::Blur => "Blur",
+            MaskStyle::Pixelate => "Pixelate",
+            MaskStyle::Black => "Black out",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A region of the cast hidden to the receivers, see `StreamingServer::set_privacy_masks`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrivacyMask {
+    /// Opposite corners, relative to the frame like the annotations
+    pub from: Point,
+    pub to: Point,
+    pub style: MaskStyle,
+}
+
+impl PrivacyMask {
+    /// The masked pixels, (x0, y0, x1, y1) with x1 and y1 excluded
+    fn bounds(&self, width: usize, height: usize) -> (usize, usize, usize, usize) {
+        let x = |v: f32| ((v.clamp(0.0, 1.0) * width as f32).round() as usize).min(width);
+        let y = |v: f32| ((v.clamp(0.0, 1.0) * height as f32).round() as usize).min(height);
+        let (x0, x1) = (x(self.from.0.min(self.to.0)), x(self.from.0.max(self.to.0)));
+        let (y0, y1) = (y(self.from.1.min(self.to.1)), y(self.from.1.max(self.to.1)));
+        (x0, y0, x1, y1)
+    }
+
+    /// Hides the region in a packed frame of `PIXEL_SIZE` bytes per pixel
+    fn apply(&self, data: &mut [u8], stride: usize, width: usize, height: usize) {
+        let bounds = self.bounds(width, height);
+        let (x0, y0, x1, y1) = bounds;
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+        match self.style {
+            MaskStyle::Black => {
+                for y in y0..y1 {
+                    data[y * stride + x0 * PIXEL_SIZE..y * stride + x1 * PIXEL_SIZE].fill(0);
+                }
+            }
+            MaskStyle::Pixelate => {
+                pixelate(data, stride, bounds, (height / PIXELATE_DIVISOR).max(4))
+            }
+            MaskStyle::Blur => {
+                for _ in 0..BLUR_PASSES {
+                    box_blur(data, stride, bounds, (height / BLUR_DIVISOR).max(2));
+                }
+            }
+        }
+    }
+}
+
+type Pixel = [u8; PIXEL_SIZE];
+
+fn get_pixel(data: &[u8], stride: usize, x: usize, y: usize) -> Pixel {
+    let i = y * stride + x * PIXEL_SIZE;
+    data[i..i + PIXEL_SIZE].try_into().unwrap()
+}
+
+fn set_pixel(data: &mut [u8], stride: usize, x: usize, y: usize, pixel: Pixel) {
+    let i = y * stride + x * PIXEL_SIZE;
+    data[i..i + PIXEL_SIZE].copy_from_slice(&pixel);
+}
+
+/// Fills every block with its average color
+fn pixelate(
+    data: &mut [u8],
+    stride: usize,
+    (x0, y0, x1, y1): (usize, usize, usize, usize),
+    block: usize,
+) {
+    for by in (y0..y1).step_by(block) {
+        for bx in (x0..x1).step_by(block) {
+            let (ex, ey) = ((bx + block).min(x1), (by + block).min(y1));
+            let mut sum = [0u32; PIXEL_SIZE];
+            for y in by..ey {
+                for x in bx..ex {
+                    let pixel = get_pixel(data, stride, x, y);
+                    for (s, v) in sum.iter_mut().zip(&pixel) {
+                        *s += *v as u32;
+                    }
+                }
+            }
+            let count = ((ex - bx) * (ey - by)) as u32;
+            let average = sum.map(|s| (s / count) as u8);
+            for y in by..ey {
+                for x in bx..ex {
+                    set_pixel(data, stride, x, y, average);
+                }
+            }
+        }
+    }
+}
+
+/// Horizontal then vertical box blur, pixels outside the region are not read
+fn box_blur(
+    data: &mut [u8],
+    stride: usize,
+    (x0, y0, x1, y1): (usize, usize, usize, usize),
+    radius: usize,
+) {
+    let mut line = Vec::with_capacity((x1 - x0).max(y1 - y0));
+    for y in y0..y1 {
+        line.clear();
+        line.extend((x0..x1).map(|x| get_pixel(data, stride, x, y)));
+        blur_line(&line, radius, |i, pixel| {
+            set_pixel(data, stride, x0 + i, y, pixel)
+        });
+    }
+    for x in x0..x1 {
+        line.clear();
+        line.extend((y0..y1).map(|y| get_pixel(data, stride, x, y)));
+        blur_line(&line, radius, |i, pixel| {
+            set_pixel(data, stride, x, y0 + i, pixel)
+        });
+    }
+}
+
+/// Average of a sliding window of `2 * radius + 1` pixels, shorter at the ends of the line
+fn blur_line(line: &[Pixel], radius: usize, mut set: impl FnMut(usize, Pixel)) {
+    let mut sum = [0u32; PIXEL_SIZE];
+    let mut count = 0;
+    for pixel in &line[..(radius + 1).min(line.len())] {
+        for (s, v) in sum.iter_mut().zip(pixel) {
+            *s += *v as u32;
+        }
+        count += 1;
+    }
+
+    for i in 0..line.len() {
+        set(i, sum.map(|s| (s / count) as u8));
+        if let Some(entering) = line.get(i + radius + 1) {
+            for (s, v) in sum.iter_mut().zip(entering) {
+                *s += *v as u32;
+            }
+            count += 1;
+        }
+        if i >= radius {
+            let leaving = &line[i - radius];
+            for (s, v) in sum.iter_mut().zip(leaving) {
+                *s -= *v as u32;
+            }
+            count -= 1;
+        }
+    }
+}
+
+/// Hides the masked regions of the frames going through a pad, whose caps are `PRIVACY_FORMAT`
+#[derive(Default)]
+pub(crate) struct MaskLayer {
+    masks: Mutex<Vec<PrivacyMask>>,
+}
+
+impl MaskLayer {
+    pub fn set(&self, masks: Vec<PrivacyMask>) {
+        *self.masks.lock().unwrap() = masks;
+    }
+
+    pub fn attach(self: &Arc<Self>, pad: &gst::Pad) {
+        let layer = self.clone();
+        pad.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+            let masks = layer.masks.lock().unwrap().clone();
+            if masks.is_empty() {
+                return gst::PadProbeReturn::Ok;
+            }
+            let Some(video_info) = pad
+                .current_caps()
+                .and_then(|caps| gst_video::VideoInfo::from_caps(&caps).ok())
+            else {
+                return gst::PadProbeReturn::Ok;
+            };
+
+            if let Some(gst::PadProbeData::Buffer(buffer)) = &mut info.data {
+                let buffer = buffer.make_mut();
+                if let Ok(mut frame) =
+                    gst_video::VideoFrameRef::from_buffer_ref_writable(buffer, &video_info)
+                {
+                    let (width, height) = (frame.width() as usize, frame.height() as usize);
+                    let stride = frame.plane_stride()[0] as usize;
+                    if let Ok(data) = frame.plane_data_mut(0) {
+                        for mask in &masks {
+                            mask.apply(data, stride, width, height);
+                        }
+                    }
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes of padding at the end of the rows, like in real frames
+    const PADDING: usize = 8;
+
+    fn gray(value: u8) -> Pixel {
+        [value, value, value, 255]
+    }
+
+    /// A `width`x`height` BGRx frame filled by `pixel(x, y)`, with its stride
+    fn frame(
+        width: usize,
+        height: usize,
+        pixel: impl Fn(usize, usize) -> Pixel,
+    ) -> (Vec<u8>, usize) {
+        let stride = width * PIXEL_SIZE + PADDING;
+        let mut data = vec![0xAA; stride * height];
+        for y in 0..height {
+            for x in 0..width {
+                set_pixel(&mut data, stride, x, y, pixel(x, y));
+            }
+        }
+        (data, stride)
+    }
+
+    fn mask(from: Point, to: Point, style: MaskStyle) -> PrivacyMask {
+        PrivacyMask { from, to, style }
+    }
+
+    fn padding(data: &[u8], stride: usize, width: usize) -> Vec<u8> {
+        data.chunks(stride)
+            .flat_map(|row| row[width * PIXEL_SIZE..].to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn bounds_are_clamped_to_the_frame() {
+        let inside = mask((0.25, 0.5), (0.75, 1.0), MaskStyle::Black);
+        assert_eq!(inside.bounds(8, 4), (2, 2, 6, 4));
+        // corners in any order
+        let swapped = mask((0.75, 1.0), (0.25, 0.5), MaskStyle::Black);
+        assert_eq!(swapped.bounds(8, 4), (2, 2, 6, 4));
+
+        let past_the_edges = mask((-0.5, 0.25), (1.5, 0.75), MaskStyle::Black);
+        assert_eq!(past_the_edges.bounds(8, 4), (0, 1, 8, 3));
+        let outside = mask((1.2, 1.2), (1.5, 1.5), MaskStyle::Black);
+        assert_eq!(outside.bounds(8, 4), (8, 4, 8, 4));
+    }
+
+    #[test]
+    fn masks_past_the_edges_stay_in_the_frame() {
+        let (width, height) = (8, 6);
+        for style in MaskStyle::ALL {
+            let (mut data, stride) = frame(width, height, |x, y| gray((x * 30 + y) as u8));
+            let original = data.clone();
+            mask((-1.0, 0.5), (2.0, 2.0), style).apply(&mut data, stride, width, height);
+            // the top rows and the row padding are left alone
+            assert_eq!(data[..3 * stride], original[..3 * stride], "{}", style);
+            assert_eq!(
+                padding(&data, stride, width),
+                padding(&original, stride, width)
+            );
+            assert_ne!(data, original, "{}", style);
+        }
+    }
+
+    #[test]
+    fn masks_outside_the_frame_do_nothing() {
+        let (mut data, stride) = frame(8, 4, |x, _| gray(x as u8 * 20));
+        let original = data.clone();
+        for style in MaskStyle::ALL {
+            mask((1.5, -1.0), (2.0, 2.0), style).apply(&mut data, stride, 8, 4);
+        }
+        assert_eq!(data, original);
+    }
+
+    #[test]
+    fn black_fills_the_region() {
+        let (mut data, stride) = frame(4, 2, |_, _| gray(200));
+        mask((0.5, 0.0), (1.0, 1.0), MaskStyle::Black).apply(&mut data, stride, 4, 2);
+        for y in 0..2 {
+            assert_eq!(get_pixel(&data, stride, 1, y), gray(200));
+            assert_eq!(get_pixel(&data, stride, 2, y), [0; PIXEL_SIZE]);
+            assert_eq!(get_pixel(&data, stride, 3, y), [0; PIXEL_SIZE]);
+        }
+    }
+
+    #[test]
+    fn pixelate_averages_each_block() {
+        // columns of 0 and 100, 5 wide so that the last block is cut by the region
+        let (mut data, stride) = frame(5, 2, |x, _| gray(if x % 2 == 0 { 0 } else { 100 }));
+        pixelate(&mut data, stride, (0, 0, 5, 2), 2);
+        for y in 0..2 {
+            let row: Vec<Pixel> = (0..5).map(|x| get_pixel(&data, stride, x, y)).collect();
+            assert_eq!(row, [gray(50), gray(50), gray(50), gray(50), gray(0)]);
+        }
+    }
+
+    #[test]
+    fn pixelate_reads_only_the_region() {
+        let (mut data, stride) = frame(4, 1, |x, _| gray(if x < 2 { 0 } else { 200 }));
+        pixelate(&mut data, stride, (2, 0, 4, 1), 4);
+        let row: Vec<Pixel> = (0..4).map(|x| get_pixel(&data, stride, x, 0)).collect();
+        assert_eq!(row, [gray(0), gray(0), gray(200), gray(200)]);
+    }
+
+    fn blurred(line: &[Pixel], radius: usize) -> Vec<Pixel> {
+        let mut result = vec![[0; PIXEL_SIZE]; line.len()];
+        blur_line(line, radius, |i, pixel| result[i] = pixel);
+        result
+    }
+
+    #[test]
+    fn blur_line_averages_a_sliding_window() {
+        let line = [0, 0, 0, 90, 0, 0, 0].map(gray);
+        assert_eq!(blurred(&line, 1), [0, 0, 30, 30, 30, 0, 0].map(gray));
+    }
+
+    #[test]
+    fn blur_line_shortens_the_window_at_the_ends() {
+        let line = [30, 60, 90].map(gray);
+        assert_eq!(blurred(&line, 1), [45, 60, 75].map(gray));
+        // a radius longer than the line averages all of it
+        assert_eq!(blurred(&line, 5), [60, 60, 60].map(gray));
+    }
+
+    #[test]
+    fn blur_line_keeps_plain_colors() {
+        let line = [gray(123); 10];
+        assert_eq!(blurred(&line, 3), line);
+        assert!(blurred(&[], 3).is_empty());
+    }
+}
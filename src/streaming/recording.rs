@@ -0,0 +1,601 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use gstreamer::{self as gst, glib, prelude::*};
+use gstreamer_video as gst_video;
+use thiserror::Error;
+
+use super::pipeline::{add_chain, link_pads, make, BuildError};
+
+/// Interval between the fragments of a fragmented MP4, in milliseconds
+const FRAGMENT_DURATION_MS: u32 = 1000;
+/// Extension appended to a plain MP4 recording for its moov recovery file
+const RECOVERY_EXTENSION: &str = "moov-recovery";
+/// Longest wait for qtmoovrecover to repair a recording, it reads the whole file
+const RECOVERY_TIMEOUT_SECONDS: u64 = 120;
+
+#[derive(Error, Debug)]
+pub enum RecoveryError {
+    #[error("GStreamer init error: {0}")]
+    GStreamerInitError(#[from] glib::Error),
+
+    #[error("Pipeline build error: {0}")]
+    PipelineBuildError(#[from] BuildError),
+
+    #[error("GStreamer state change error: {0}")]
+    GStreamerStateChangeError(#[from] gst::StateChangeError),
+
+    #[error("Recovery failed: {0}")]
+    Failed(String),
+
+    #[error("Recovery timed out")]
+    TimedOut,
+
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// File format of the recordings. Except for plain MP4, whose index is written only
+/// at the end (see `recover`), a recording interrupted by a crash stays playable
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Container {
+    Mp4,
+    /// MP4 written in fragments, playable while being recorded
+    #[default]
+    FragmentedMp4,
+    Matroska,
+    MpegTs,
+}
+
+impl Container {
+    pub const ALL: [Container; 4] = [
+        Container::Mp4,
+        Container::FragmentedMp4,
+        Container::Matroska,
+        Container::MpegTs,
+    ];
+
+    /// `path` is the file being written, to recover it after a crash. Segments don't need it
+    fn muxer(&self, path: Option<&Path>) -> Result<gst::Element, BuildError> {
+        match self {
+            Container::Mp4 => {
+                let mux = make("mp4mux")?;
+                if let Some(path) = path {
+                    mux.set_property(
+                        "moov-recovery-file",
+                        &*recovery_file(path).to_string_lossy(),
+                    );
+                }
+                Ok(mux)
+            }
+            Container::FragmentedMp4 => {
+                let mux = make("mp4mux")?;
+                mux.set_property("fragment-duration", FRAGMENT_DURATION_MS);
+                Ok(mux)
+            }
+            Container::Matroska => make("matroskamux"),
+            Container::MpegTs => make("mpegtsmux"),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Container::Mp4 | Container::FragmentedMp4 => "mp4",
+            Container::Matroska => "mkv",
+            Container::MpegTs => "ts",
+        }
+    }
+}
+
+impl std::fmt::Display for Container {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Container::Mp4 => "MP4",
+            Container::FragmentedMp4 => "Fragmented MP4",
+            Container::Matroska => "MKV",
+            Container::MpegTs => "MPEG-TS",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Where and how recordings are saved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordingOptions {
+    pub directory: PathBuf,
+    /// File name without extension. `{caster}` is replaced by the name of the caster,
+    /// `{counter}` by the first number giving a new file and `%Y`, `%m`, `%d`, `%H`, `%M`, `%S`
+    /// (or any other `strftime` placeholder) by the current date and time
+    pub pattern: String,
+    pub container: Container,
+    /// Split the recording in several files, `None` for a single file
+    pub segmentation: Option<Segmentation>,
+}
+
+impl Default for RecordingOptions {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("."),
+            pattern: "{caster}_%Y%m%d_%H%M%S".to_string(),
+            container: Container::default(),
+            segmentation: None,
+        }
+    }
+}
+
+/// Limits of the files of a segmented recording. The segments of `name.mp4` are
+/// `name_00000.mp4`, `name_00001.mp4`, ... listed in the playlist `name.m3u`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segmentation {
+    /// Maximum length of a segment
+    pub max_duration: Option<Duration>,
+    /// Maximum size of a segment, in bytes
+    pub max_size: Option<u64>,
+    /// The oldest segments are deleted when all together they take more than this, in bytes
+    pub retention: Option<u64>,
+}
+
+impl Default for Segmentation {
+    fn default() -> Self {
+        Self {
+            max_duration: Some(Duration::from_secs(30 * 60)),
+            max_size: None,
+            retention: None,
+        }
+    }
+}
+
+impl Segmentation {
+    /// A splitmuxsink writing into `filesink`
+    fn splitmuxsink(
+        &self,
+        path: &Path,
+        container: Container,
+        filesink: &gst::Element,
+    ) -> Result<gst::Element, BuildError> {
+        let splitmux = make("splitmuxsink")?;
+        splitmux.set_property("muxer", container.muxer(None)?);
+        splitmux.set_property("sink", filesink);
+        if let Some(duration) = self.max_duration {
+            splitmux.set_property("max-size-time", duration.as_nanos() as u64);
+            // keyframes where the segments must start, otherwise they grow past the limit
+            splitmux.set_property("send-keyframe-requests", true);
+        }
+        if let Some(size) = self.max_size {
+            splitmux.set_property("max-size-bytes", size);
+        }
+
+        let index = Mutex::new(SegmentIndex {
+            path: path.to_path_buf(),
+            segments: VecDeque::new(),
+            retention: self.retention,
+        });
+        splitmux.connect("format-location", false, move |args| {
+            let id = args[1].get::<u32>().unwrap();
+            let segment = index.lock().unwrap().next(id);
+            Some(segment.to_string_lossy().to_value())
+        });
+
+        Ok(splitmux)
+    }
+}
+
+/// The segments of a recording, kept in a playlist
+struct SegmentIndex {
+    /// Path of the whole recording, the segments are named after it
+    path: PathBuf,
+    segments: VecDeque<PathBuf>,
+    retention: Option<u64>,
+}
+
+impl SegmentIndex {
+    /// Path of a new segment, called when the previous one is complete
+    fn next(&mut self, id: u32) -> PathBuf {
+        if let Some(retention) = self.retention {
+            let size = |path: &PathBuf| fs::metadata(path).map_or(0, |m| m.len());
+            let mut total: u64 = self.segments.iter().map(size).sum();
+            while total > retention {
+                let Some(oldest) = self.segments.pop_front() else {
+                    break;
+                };
+                total -= size(&oldest);
+                let _ = fs::remove_file(&oldest);
+            }
+        }
+
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = self.path.extension().unwrap_or_default().to_string_lossy();
+        let segment = self
+            .path
+            .with_file_name(format!("{}_{:05}.{}", stem, id, extension));
+        self.segments.push_back(segment.clone());
+
+        let mut playlist = "#EXTM3U\n".to_string();
+        for segment in &self.segments {
+            if let Some(name) = segment.file_name() {
+                playlist.push_str(&name.to_string_lossy());
+                playlist.push('\n');
+            }
+        }
+        if let Err(e) = fs::write(playlist_path(&self.path), playlist) {
+            println!("Can't write the segment index: {}", e);
+        }
+
+        segment
+    }
+}
+
+/// Playlist of the segments of the recording `path`
+fn playlist_path(path: &Path) -> PathBuf {
+    path.with_extension("m3u")
+}
+
+impl RecordingOptions {
+    /// Path of a new recording of `caster`
+    pub fn path(&self, caster: &str) -> PathBuf {
+        let mut name = String::new();
+        if write!(name, "{}", Local::now().format(&self.pattern)).is_err() {
+            // invalid placeholder, better a weird name than no recording
+            name = self.pattern.clone();
+        }
+        let caster: String = caster
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || "-_.".contains(c) {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let name = name.replace("{caster}", &caster);
+
+        let file = |name: &str| {
+            self.directory
+                .join(format!("{}.{}", name, self.container.extension()))
+        };
+        if !name.contains("{counter}") {
+            return file(&name);
+        }
+        (1..)
+            .map(|counter: u32| file(&name.replace("{counter}", &counter.to_string())))
+            .find(|path| !path.exists())
+            .unwrap()
+    }
+}
+
+/// A `queue ! h264parse ! mux ! filesink` branch attached to the tee of an encoded
+/// H264 stream while the pipeline is running
+pub(crate) struct Recorder {
+    pipeline: gst::Pipeline,
+    tee: gst::Element,
+    tee_pad: gst::Pad,
+    elements: Vec<gst::Element>,
+    filesink: gst::Element,
+    /// The recorded file, or the playlist of the segments
+    path: PathBuf,
+}
+
+impl Recorder {
+    /// Starts recording to `path`, the rest of `options` chooses the format
+    pub fn attach(
+        pipeline: &gst::Pipeline,
+        tee: &gst::Element,
+        path: &Path,
+        options: &RecordingOptions,
+    ) -> Result<Self, BuildError> {
+        let queue = make("queue")?;
+        let parse = make("h264parse")?;
+        let filesink = make("filesink")?;
+        // the file is finalized by the EOS of this branch only, not of the whole pipeline
+        filesink.set_property("async", false);
+
+        let (elements, path) = match &options.segmentation {
+            None => {
+                filesink.set_property("location", &*path.to_string_lossy());
+                let mux = options.container.muxer(Some(path))?;
+                let elements = vec![queue, parse, mux, filesink.clone()];
+                add_chain(pipeline, &elements)?;
+                (elements, path.to_path_buf())
+            }
+            Some(segmentation) => {
+                // splitmuxsink sets the location of the filesink for every segment
+                let splitmux = segmentation.splitmuxsink(path, options.container, &filesink)?;
+                let elements = vec![queue, parse, splitmux];
+                add_chain(pipeline, &elements[..2])?;
+                pipeline.add(&elements[2])?;
+                link_pads(
+                    &elements[1].static_pad("src").unwrap(),
+                    &elements[2].request_pad_simple("video").unwrap(),
+                )?;
+                (elements, playlist_path(path))
+            }
+        };
+        for element in &elements {
+            element.sync_state_with_parent()?;
+        }
+
+        let queue_sink = elements[0].static_pad("sink").unwrap();
+        let tee_pad = tee.request_pad_simple("src_%u").unwrap();
+        link_pads(&tee_pad, &queue_sink)?;
+        // the file must start with a keyframe, asked upstream to the encoder
+        tee_pad.send_event(
+            gst_video::UpstreamForceKeyUnitEvent::builder()
+                .all_headers(true)
+                .build(),
+        );
+
+        Ok(Self {
+            pipeline: pipeline.clone(),
+            tee: tee.clone(),
+            tee_pad,
+            elements,
+            filesink,
+            path,
+        })
+    }
+
+    /// The recorded file, or the playlist of the segments
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Unlinks the branch and finalizes the file without stopping the rest of the pipeline,
+    /// `on_finished` is called from another thread once the file is complete
+    pub fn detach(self, on_finished: impl FnOnce(PathBuf) + Send + 'static) {
+        let Recorder {
+            pipeline,
+            tee,
+            tee_pad,
+            elements,
+            filesink,
+            path,
+        } = self;
+
+        let queue_sink = elements[0].static_pad("sink").unwrap();
+        let filesink_sink = filesink.static_pad("sink").unwrap();
+
+        // the muxer writes its headers on EOS, after that the branch can be removed
+        let finished = Mutex::new(Some((pipeline, elements, path, on_finished)));
+        filesink_sink.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            let is_eos = info
+                .event()
+                .is_some_and(|event| event.type_() == gst::EventType::Eos);
+            if !is_eos {
+                return gst::PadProbeReturn::Ok;
+            }
+            if let Some((pipeline, elements, path, on_finished)) = finished.lock().unwrap().take() {
+                // elements can't change state from their own streaming thread
+                thread::spawn(move || {
+                    for element in &elements {
+                        let _ = element.set_state(gst::State::Null);
+                    }
+                    let _ = pipeline.remove_many(&elements);
+                    // the file is complete, there's nothing left to recover
+                    let _ = std::fs::remove_file(recovery_file(&path));
+                    on_finished(path);
+                });
+            }
+            // the rest of the pipeline is still running, it must not see this EOS
+            gst::PadProbeReturn::Drop
+        });
+
+        tee_pad.add_probe(gst::PadProbeType::IDLE, move |pad, _| {
+            let _ = pad.unlink(&queue_sink);
+            tee.release_request_pad(pad);
+            queue_sink.send_event(gst::event::Eos::new());
+            gst::PadProbeReturn::Remove
+        });
+    }
+
+    /// Like `detach`, but waits at most `timeout` for the file to be complete.
+    /// Returns its path if it was
+    pub fn detach_and_wait(self, timeout: Duration) -> Option<PathBuf> {
+        let (tx, rx) = channel();
+        self.detach(move |path| {
+            let _ = tx.send(path);
+        });
+        rx.recv_timeout(timeout).ok()
+    }
+}
+
+fn recovery_file(path: &Path) -> PathBuf {
+    let mut file = path.as_os_str().to_owned();
+    file.push(".");
+    file.push(RECOVERY_EXTENSION);
+    PathBuf::from(file)
+}
+
+/// The MP4 recordings in `directory` that were interrupted before being finalized
+pub fn interrupted_recordings(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut recordings: Vec<PathBuf> = std::fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == RECOVERY_EXTENSION))
+        .map(|p| p.with_extension(""))
+        .filter(|p| p.exists())
+        .collect();
+    recordings.sort();
+    Ok(recordings)
+}
+
+/// Rebuilds the index of an interrupted MP4 recording from its recovery file,
+/// replacing the broken file with the repaired one
+pub fn recover(path: &Path) -> Result<(), RecoveryError> {
+    gst::init()?;
+
+    let mut fixed = path.as_os_str().to_owned();
+    fixed.push(".recovered");
+    let fixed = PathBuf::from(fixed);
+
+    let recover = make("qtmoovrecover")?;
+    recover.set_property("recovery-input", &*recovery_file(path).to_string_lossy());
+    recover.set_property("broken-input", &*path.to_string_lossy());
+    recover.set_property("fixed-output", &*fixed.to_string_lossy());
+
+    let pipeline = gst::Pipeline::new();
+    pipeline.add(&recover).map_err(BuildError::from)?;
+    pipeline.set_state(gst::State::Playing)?;
+    let message = pipeline.bus().and_then(|bus| {
+        bus.timed_pop_filtered(
+            gst::ClockTime::from_seconds(RECOVERY_TIMEOUT_SECONDS),
+            &[gst::MessageType::Eos, gst::MessageType::Error],
+        )
+    });
+    let _ = pipeline.set_state(gst::State::Null);
+
+    match message.as_ref().map(|m| m.view()) {
+        Some(gst::MessageView::Eos(_)) => {
+            std::fs::rename(&fixed, path)?;
+            std::fs::remove_file(recovery_file(path))?;
+            Ok(())
+        }
+        Some(gst::MessageView::Error(err)) => {
+            let _ = std::fs::remove_file(&fixed);
+            Err(RecoveryError::Failed(err.error().to_string()))
+        }
+        _ => {
+            let _ = std::fs::remove_file(&fixed);
+            Err(RecoveryError::TimedOut)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for each test
+    fn test_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("rust_streamer_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn options(directory: &Path, pattern: &str) -> RecordingOptions {
+        RecordingOptions {
+            directory: directory.to_path_buf(),
+            pattern: pattern.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn caster_names_are_sanitized() {
+        let options = options(Path::new("records"), "{caster}_rec");
+        assert_eq!(
+            options.path("Alice's PC/1.0"),
+            Path::new("records").join("Alice_s_PC_1.0_rec.mp4")
+        );
+    }
+
+    #[test]
+    fn dates_are_formatted() {
+        let options = options(Path::new("."), "%Y_{caster}");
+        let year = Local::now().year().to_string();
+        assert_eq!(
+            options.path("bob"),
+            Path::new(".").join(format!("{}_bob.mp4", year))
+        );
+    }
+
+    #[test]
+    fn invalid_placeholders_are_kept() {
+        let options = options(Path::new("."), "rec_%Q");
+        assert_eq!(options.path("bob"), Path::new(".").join("rec_%Q.mp4"));
+    }
+
+    #[test]
+    fn extension_follows_the_container() {
+        for container in Container::ALL {
+            let options = RecordingOptions {
+                container,
+                ..options(Path::new("."), "rec")
+            };
+            let path = options.path("bob");
+            assert_eq!(path.extension().unwrap(), container.extension());
+        }
+    }
+
+    #[test]
+    fn counter_skips_existing_files() {
+        let directory = test_directory("counter");
+        let options = options(&directory, "{caster}_{counter}");
+        assert_eq!(options.path("bob"), directory.join("bob_1.mp4"));
+
+        fs::write(directory.join("bob_1.mp4"), b"").unwrap();
+        fs::write(directory.join("bob_2.mp4"), b"").unwrap();
+        assert_eq!(options.path("bob"), directory.join("bob_3.mp4"));
+        // another caster starts over
+        assert_eq!(options.path("alice"), directory.join("alice_1.mp4"));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    fn playlist(path: &Path) -> String {
+        fs::read_to_string(playlist_path(path)).unwrap()
+    }
+
+    #[test]
+    fn segments_are_listed_in_the_playlist() {
+        let directory = test_directory("playlist");
+        let path = directory.join("rec.mkv");
+        let mut index = SegmentIndex {
+            path: path.clone(),
+            segments: VecDeque::new(),
+            retention: None,
+        };
+
+        assert_eq!(index.next(0), directory.join("rec_00000.mkv"));
+        assert_eq!(playlist(&path), "#EXTM3U\nrec_00000.mkv\n");
+        assert_eq!(index.next(1), directory.join("rec_00001.mkv"));
+        assert_eq!(playlist(&path), "#EXTM3U\nrec_00000.mkv\nrec_00001.mkv\n");
+        assert_eq!(playlist_path(&path), directory.join("rec.m3u"));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn oldest_segments_are_deleted_past_the_retention() {
+        let directory = test_directory("retention");
+        let path = directory.join("rec.mp4");
+        let mut index = SegmentIndex {
+            path: path.clone(),
+            segments: VecDeque::new(),
+            retention: Some(250),
+        };
+
+        let mut segments = Vec::new();
+        for id in 0..4 {
+            let segment = index.next(id);
+            fs::write(&segment, [0; 100]).unwrap();
+            segments.push(segment);
+        }
+        // 300 bytes when the fourth segment starts, the first one goes
+        assert!(!segments[0].exists());
+        assert!(segments[1..].iter().all(|segment| segment.exists()));
+        assert_eq!(
+            playlist(&path),
+            "#EXTM3U\nrec_00001.mp4\nrec_00002.mp4\nrec_00003.mp4\n"
+        );
+
+        index.next(4);
+        // 300 bytes again, one more goes
+        assert!(!segments[1].exists());
+        assert_eq!(
+            playlist(&path),
+            "#EXTM3U\nrec_00002.mp4\nrec_00003.mp4\nrec_00004.mp4\n"
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,13 +1,27 @@
-use byte_slice_cast::*;
 use std::io;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use gst::glib;
 use gst::prelude::*;
-use gst::{element_error, glib};
 use gstreamer as gst;
 use gstreamer_app as gst_app;
+use gstreamer_video as gst_video;
 use thiserror::Error;
 
+use super::annotation::{Annotation, AnnotationLayer};
+use super::blank::{BlankContent, BlankError, BlankSource};
+use super::bus;
+use super::event::{EventBus, StatsCounter, StreamingEvent, Subscription};
+use super::frame::{self, pull_frame, VideoFrame};
+use super::pipeline::{
+    self, BuildError, NetworkBranch, OutputSettings, RecordingBranch, ServerConfig, ServerPipeline,
+};
+use super::privacy::{MaskLayer, PrivacyMask};
+use super::recording::{Recorder, RecordingOptions};
+use super::simulcast::LayerSinks;
+use super::snapshot::{self, LatestFrame, SnapshotError, SnapshotFormat};
 use crate::connection::server::ConnectionServer;
 
 #[derive(Error, Debug)]
@@ -18,108 +32,168 @@ pub enum StreamingServerError {
     #[error("GStreamer element error: {0}")]
     GStreamerElementCreationError(#[from] glib::BoolError),
 
+    #[error("Pipeline build error: {0}")]
+    PipelineBuildError(#[from] BuildError),
+
     #[error("GStreamer state change error: {0}")]
     GStreamerStateChangeError(#[from] gst::StateChangeError),
 
     #[error("Websocket error: {0}")]
     WebsocketError(#[from] io::Error),
+
+    #[error("Already recording")]
+    AlreadyRecording,
+}
+
+/// Where the recording is taken from, see `StreamingServer::start_recording`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordingTap {
+    /// The H264 of `network[0]`, saved without encoding it again
+    Network,
+    /// The recording branch, encoding the recording on its own
+    Recording,
 }
 
+/// Maximum time to wait for a recording to be finalized when the server is dropped
+const RECORDING_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct StreamingServer {
-    #[cfg(any(target_os = "windows", target_os = "linux"))]
-    source: gst::Element,
+    /// The screen capture element, `None` when casting another source
+    screen: Option<gst::Element>,
+    /// The captured monitor, areas are relative to it
+    #[cfg(target_os = "linux")]
+    monitor: Option<super::source::Monitor>,
 
     pipeline: gst::Pipeline,
 
     #[cfg(target_os = "macos")]
-    crop: gst::Element,
+    crop: Option<gst::Element>,
 
     selector: gst::Element,
+    capture_pad: gst::Pad,
+    blank_pad: gst::Pad,
+    blank: Arc<BlankSource>,
 
-    _connection_server: ConnectionServer,
-}
+    masks: Arc<MaskLayer>,
+    annotations: Arc<AnnotationLayer>,
 
-impl StreamingServer {
-    pub fn new(
-        mut image_parser: impl FnMut(&[u8]) + Send + 'static,
-    ) -> Result<Self, StreamingServerError> {
-        gst::init()?;
+    network: Vec<NetworkBranch>,
+    layers: Arc<LayerSinks>,
+    recording: RecordingBranch,
+    /// The recordings get watermarks the receivers don't, so they can't reuse `network[0]`
+    stamped_recordings: bool,
+    /// The last `OutputSettings` given to `set_output`
+    output: Mutex<OutputSettings>,
 
-        let pipeline_string = if cfg!(target_os = "windows") {
-            "input-selector name=i ! tee name=t ! queue ! videoconvert ! x264enc tune=zerolatency ! rtph264pay ! multiudpsink name=s t. ! queue ! videoconvert ! jpegenc ! appsink max-buffers=1 caps=image/jpeg name=videosink d3d11screencapturesrc show-cursor=true name=src ! video/x-raw,framerate=30/1 ! i.sink_0 videotestsrc pattern=white ! video/x-raw,framerate=30/1 ! i.sink_1"
-        } else if cfg!(target_os = "linux") {
-            "input-selector name=i ! tee name=t ! queue ! videoconvert ! x264enc tune=zerolatency ! rtph264pay ! multiudpsink name=s t. ! queue ! videoconvert ! jpegenc ! appsink max-buffers=1 caps=image/jpeg name=videosink ximagesrc use-damage=false name=src ! video/x-raw,framerate=30/1 ! videoconvert ! i.sink_0 videotestsrc pattern=white ! video/x-raw,framerate=30/1 ! i.sink_1"
-        } else {
-            "input-selector name=i ! tee name=t ! queue ! videoconvert ! x264enc tune=zerolatency ! rtph264pay ! multiudpsink name=s t. ! queue ! videoconvert ! jpegenc ! appsink max-buffers=1 caps=image/jpeg name=videosink avfvideosrc capture-screen=1 capture-screen-cursor=1 name=src ! video/x-raw,framerate=30/1 ! videocrop name=crop ! videoconvert ! i.sink_0 videotestsrc pattern=white ! video/x-raw,framerate=30/1 ! videoconvert ! i.sink_1"
-        };
+    recorder: Mutex<Option<(Recorder, RecordingTap)>>,
+    latest_frame: LatestFrame,
+    /// Last captured buffer, without the masks and annotations, frozen by `BlankContent::FrozenFrame`
+    latest_capture: Arc<Mutex<Option<gst::Buffer>>>,
 
-        // can't panic after pipeline is created correctly
-        let pipeline = gst::parse::launch(&pipeline_string)?
-            .dynamic_cast::<gst::Pipeline>()
-            .unwrap();
-        let multiudpsink = pipeline.by_name("s").unwrap();
-        let videosink = pipeline
-            .by_name("videosink")
-            .unwrap()
-            .dynamic_cast::<gst_app::AppSink>()
-            .unwrap();
+    connection_server: ConnectionServer,
 
-        #[cfg(any(target_os = "windows", target_os = "linux"))]
-        let source = pipeline.by_name("src").unwrap();
+    events: EventBus,
+}
 
-        #[cfg(target_os = "macos")]
-        let crop = pipeline.by_name("crop").unwrap();
+impl StreamingServer {
+    pub fn new(config: ServerConfig) -> Result<Self, StreamingServerError> {
+        gst::init()?;
 
-        let selector = pipeline.by_name("i").unwrap();
+        #[cfg(target_os = "linux")]
+        let monitor = match &config.source {
+            super::source::CaptureSource::Screen { monitor } => *monitor,
+            _ => None,
+        };
 
-        let multiudpsink = Arc::new(multiudpsink);
-        let multiudpsink2 = multiudpsink.clone();
+        let ServerPipeline {
+            pipeline,
+            screen,
+            crop,
+            selector,
+            capture_pad,
+            blank_pad,
+            blank,
+            masking,
+            annotation,
+            network,
+            recording,
+            appsink,
+        } = pipeline::build_server(&config)?;
+        // only macOS crops after the capture
+        #[cfg(not(target_os = "macos"))]
+        let _ = crop;
+        let stamped_recordings =
+            config.watermarks.recordings_only && !config.watermarks.watermarks.is_empty();
+
+        let masks = Arc::new(MaskLayer::default());
+        masks.attach(&masking.static_pad("src").unwrap());
+        let annotations = Arc::new(AnnotationLayer::default());
+        annotations.attach(&annotation);
+
+        let events = EventBus::default();
+        bus::watch(&pipeline, events.clone());
+
+        let layers = Arc::new(LayerSinks::new(
+            network
+                .iter()
+                .map(|branch| branch.multiudpsink.clone())
+                .collect(),
+        ));
+        let layers_clone = layers.clone();
+        let layers_clone2 = layers.clone();
+        let events_clone = events.clone();
+        let events_clone2 = events.clone();
+        let default_layer = config.default_layer;
         let connection_server = ConnectionServer::new(
             move |ip| {
-                multiudpsink.emit_by_name_with_values("add", &[ip.into(), 9001.into()]);
-                println!("Connected: {}", ip);
+                let layer = layers_clone.add(ip, default_layer);
+                events_clone.emit(StreamingEvent::ReceiverJoined(ip.to_string()));
+                events_clone.emit(StreamingEvent::ReceiverLayerChanged(ip.to_string(), layer));
             },
             move |ip| {
-                multiudpsink2.emit_by_name_with_values("remove", &[ip.into(), 9001.into()]);
-                println!("Disconnected: {}", ip);
+                layers_clone2.remove(ip);
+                events_clone2.emit(StreamingEvent::ReceiverLeft(ip.to_string()));
             },
         )?;
 
-        videosink.set_callbacks(
+        // the inactive selector pad still gets the captured frames while blanked
+        let latest_capture = Arc::new(Mutex::new(None));
+        let latest_capture_clone = latest_capture.clone();
+        capture_pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+            if let Some(buffer) = info.buffer() {
+                *latest_capture_clone.lock().unwrap() = Some(buffer.clone());
+            }
+            gst::PadProbeReturn::Ok
+        });
+        // the blank screen follows the capture size so that switching doesn't renegotiate
+        let blank_clone = blank.clone();
+        capture_pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            if let Some(gst::EventView::Caps(caps)) = info.event().map(|event| event.view()) {
+                if let Ok(video) = gst_video::VideoInfo::from_caps(caps.caps()) {
+                    blank_clone.set_size(video.width(), video.height());
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+
+        let events_clone = events.clone();
+        let layers_clone = layers.clone();
+        let latest_frame = LatestFrame::default();
+        let latest_frame_clone = latest_frame.clone();
+        let mut stats = StatsCounter::new();
+
+        let frame_format = config.frame_format;
+        appsink.set_callbacks(
             gst_app::AppSinkCallbacks::builder()
                 .new_sample(move |appsink| {
-                    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
-                    let buffer = sample.buffer().ok_or_else(|| {
-                        element_error!(
-                            appsink,
-                            gst::ResourceError::Failed,
-                            ("Failed to get buffer from appsink")
-                        );
-
-                        gst::FlowError::Error
-                    })?;
-
-                    let map = buffer.map_readable().map_err(|_| {
-                        element_error!(
-                            appsink,
-                            gst::ResourceError::Failed,
-                            ("Failed to map buffer readable")
-                        );
-
-                        gst::FlowError::Error
-                    })?;
-
-                    let samples = map.as_slice_of::<u8>().map_err(|_| {
-                        element_error!(
-                            appsink,
-                            gst::ResourceError::Failed,
-                            ("Failed to interpret buffer as bytes")
-                        );
-
-                        gst::FlowError::Error
-                    })?;
-
-                    image_parser(samples);
+                    let frame = Arc::new(pull_frame(appsink, frame_format)?);
+
+                    *latest_frame_clone.lock().unwrap() = Some(frame.clone());
+                    events_clone.emit(StreamingEvent::FrameReady(frame));
+                    if let Some(mut stats) = stats.frame() {
+                        stats.receivers = layers_clone.receivers();
+                        events_clone.emit(StreamingEvent::StatsUpdated(stats));
+                    }
 
                     Ok(gst::FlowSuccess::Ok)
                 })
@@ -127,8 +201,9 @@ impl StreamingServer {
         );
 
         Ok(Self {
-            #[cfg(any(target_os = "windows", target_os = "linux"))]
-            source,
+            screen,
+            #[cfg(target_os = "linux")]
+            monitor,
 
             pipeline,
 
@@ -136,11 +211,33 @@ impl StreamingServer {
             crop,
 
             selector,
+            capture_pad,
+            blank_pad,
+            blank,
+
+            masks,
+            annotations,
+
+            network,
+            layers,
+            recording,
 
-            _connection_server: connection_server,
+            stamped_recordings,
+            output: Mutex::new(config.output),
+            recorder: Mutex::new(None),
+            latest_frame,
+            latest_capture,
+
+            connection_server,
+
+            events,
         })
     }
 
+    pub fn subscribe(&self) -> Subscription {
+        self.events.subscribe()
+    }
+
     pub fn start(&self) -> Result<(), StreamingServerError> {
         Ok(self.pipeline.set_state(gst::State::Playing).map(|_| ())?)
     }
@@ -150,49 +247,198 @@ impl StreamingServer {
     }
 
     #[cfg(target_os = "linux")]
-    /// startx, starty are the top left corner of the rectangle, endx, endy are the bottom right corner of the rectangle
+    /// startx, starty are the top left corner of the rectangle, endx, endy are the bottom right corner of the rectangle,
+    /// relative to the captured monitor. The bottom right corner is excluded, the area is `endx - startx` wide
     pub fn capture_resize(&self, startx: u32, starty: u32, endx: u32, endy: u32) {
-        self.source.set_property("startx", startx);
-        self.source.set_property("starty", starty);
-        self.source.set_property("endx", endx);
-        self.source.set_property("endy", endy);
+        let fullscreen = (startx, starty, endx, endy) == (0, 0, 0, 0);
+        // ximagesrc includes the bottom right corner
+        let (endx, endy) = (endx.saturating_sub(1), endy.saturating_sub(1));
+        let (startx, starty, endx, endy) = match &self.monitor {
+            Some(monitor) if fullscreen => monitor.bounds(),
+            None if fullscreen => (0, 0, 0, 0),
+            Some(monitor) => {
+                let (x, y, maxx, maxy) = monitor.bounds();
+                (
+                    x + startx,
+                    y + starty,
+                    (x + endx).min(maxx),
+                    (y + endy).min(maxy),
+                )
+            }
+            None => (startx, starty, endx, endy),
+        };
+        if let Some(screen) = &self.screen {
+            screen.set_property("startx", startx);
+            screen.set_property("starty", starty);
+            screen.set_property("endx", endx);
+            screen.set_property("endy", endy);
+        }
     }
 
     #[cfg(target_os = "windows")]
     /// startx, starty are the top left corner of the rectangle, endx, endy are the bottom right corner of the rectangle
     pub fn capture_resize(&self, startx: u32, starty: u32, endx: u32, endy: u32) {
-        self.source.set_property("crop-x", startx);
-        self.source.set_property("crop-y", starty);
-        self.source.set_property("crop-width", endx - startx);
-        self.source.set_property("crop-height", endy - starty);
+        if let Some(screen) = &self.screen {
+            screen.set_property("crop-x", startx);
+            screen.set_property("crop-y", starty);
+            screen.set_property("crop-width", endx - startx);
+            screen.set_property("crop-height", endy - starty);
+        }
     }
 
     #[cfg(target_os = "macos")]
     /// the parameters are the number of pixels to remove from the left, top, right and bottom of the screen
     pub fn capture_resize(&self, left: u32, top: u32, right: u32, bottom: u32) {
-        self.crop.set_property("left", left);
-        self.crop.set_property("top", top);
-        self.crop.set_property("right", right);
-        self.crop.set_property("bottom", bottom);
+        if let Some(crop) = &self.crop {
+            crop.set_property("left", left as i32);
+            crop.set_property("top", top as i32);
+            crop.set_property("right", right as i32);
+            crop.set_property("bottom", bottom as i32);
+        }
     }
 
     pub fn capture_fullscreen(&self) {
         self.capture_resize(0, 0, 0, 0);
     }
 
+    /// Changes the size and framerate of the stream sent to the receivers, also while casting.
+    /// Simulcast layers keep their own size, and recordings the captured size. While the network
+    /// stream is recorded as is, it gets the new output once the recording stops
+    pub fn set_output(&self, output: OutputSettings) {
+        *self.output.lock().unwrap() = output;
+        let recording_network = matches!(
+            *self.recorder.lock().unwrap(),
+            Some((_, RecordingTap::Network))
+        );
+        for (i, branch) in self.network.iter().enumerate() {
+            // the muxer of the recording can't follow a change of size or framerate
+            if i == 0 && recording_network {
+                continue;
+            }
+            branch.apply_output(output);
+        }
+    }
+
+    /// Sends another simulcast layer to a connected receiver, layers only change when asked
+    pub fn set_receiver_layer(&self, ip: &str, layer: usize) {
+        if let Some(layer) = self.layers.switch(ip, layer) {
+            self.events
+                .emit(StreamingEvent::ReceiverLayerChanged(ip.to_string(), layer));
+        }
+    }
+
+    /// Records the cast to `path` (usually `options.path(..)`) while it goes on, at the captured
+    /// size and with all the watermarks. The H264 sent to the receivers is saved as is when it
+    /// matches, otherwise the recording is encoded on its own
+    pub fn start_recording(
+        &self,
+        path: &Path,
+        options: &RecordingOptions,
+    ) -> Result<(), StreamingServerError> {
+        let mut recorder = self.recorder.lock().unwrap();
+        if recorder.is_some() {
+            return Err(StreamingServerError::AlreadyRecording);
+        }
+        let output = *self.output.lock().unwrap();
+        let native = self.network[0].height.is_none() && output.height.is_none();
+        let tap = if native && !self.stamped_recordings {
+            RecordingTap::Network
+        } else {
+            RecordingTap::Recording
+        };
+        let encoded = match tap {
+            RecordingTap::Network => &self.network[0].encoder.encoded,
+            RecordingTap::Recording => &self.recording.encoder.encoded,
+        };
+        let new_recorder = Recorder::attach(&self.pipeline, encoded, path, options)?;
+        if tap == RecordingTap::Recording {
+            self.recording.valve.set_property("drop", false);
+        }
+        self.events.emit(StreamingEvent::RecordingStarted(
+            new_recorder.path().to_path_buf(),
+        ));
+        *recorder = Some((new_recorder, tap));
+        Ok(())
+    }
+
+    /// Finalizes the recording, `StreamingEvent::RecordingFinished` is sent once the file is complete
+    pub fn stop_recording(&self) {
+        let Some((recorder, tap)) = self.recorder.lock().unwrap().take() else {
+            return;
+        };
+        let events = self.events.clone();
+        recorder.detach(move |path| events.emit(StreamingEvent::RecordingFinished(path)));
+        match tap {
+            RecordingTap::Network => {
+                // the output may have changed meanwhile
+                let output = *self.output.lock().unwrap();
+                self.network[0].apply_output(output);
+            }
+            RecordingTap::Recording => self.recording.valve.set_property("drop", true),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.lock().unwrap().is_some()
+    }
+
+    /// Saves the frame the receivers see now in `directory`, returns the path of the image
+    pub fn snapshot(
+        &self,
+        directory: &Path,
+        format: SnapshotFormat,
+    ) -> Result<PathBuf, SnapshotError> {
+        snapshot::save(&self.latest_frame, directory, format)
+    }
+
+    /// Replaces the regions hidden to the receivers and the recordings, also while casting
+    pub fn set_privacy_masks(&self, masks: Vec<PrivacyMask>) {
+        self.masks.set(masks);
+    }
+
+    /// Replaces the marks drawn over the cast, receivers and recordings see them from the next frame
+    pub fn set_annotations(&self, annotations: Vec<Annotation>) {
+        self.annotations.set(annotations);
+    }
+
+    /// Shows the blank screen content instead of the capture
     pub fn blank_screen(&self) {
-        self.selector
-            .set_property("active-pad", &self.selector.static_pad("sink_1").unwrap());
+        self.selector.set_property("active-pad", &self.blank_pad);
+    }
+
+    /// Replaces the blank screen content and shows it, also when already blanked
+    pub fn blank_screen_with(&self, content: BlankContent) -> Result<(), BlankError> {
+        let frozen = match content {
+            BlankContent::FrozenFrame => self.frozen_frame(),
+            _ => None,
+        };
+        self.blank.set(content, frozen)?;
+        self.blank_screen();
+        Ok(())
     }
 
     pub fn restore_screen(&self) {
-        self.selector
-            .set_property("active-pad", &self.selector.static_pad("sink_0").unwrap());
+        self.selector.set_property("active-pad", &self.capture_pad);
+    }
+
+    /// The last captured frame as it was before the selector and the overlays
+    fn frozen_frame(&self) -> Option<VideoFrame> {
+        let buffer = self.latest_capture.lock().unwrap().clone()?;
+        let caps = self.capture_pad.current_caps()?;
+        let sample = gst::Sample::builder().buffer(&buffer).caps(&caps).build();
+        frame::to_rgba_frame(&sample)
     }
 }
 
 impl Drop for StreamingServer {
     fn drop(&mut self) {
+        self.connection_server.notify_stream_ending();
+        if let Some((recorder, _)) = self.recorder.lock().unwrap().take() {
+            match recorder.detach_and_wait(RECORDING_TIMEOUT) {
+                Some(path) => self.events.emit(StreamingEvent::RecordingFinished(path)),
+                None => println!("Timed out finalizing the recording"),
+            }
+        }
         let _ = self.pipeline.set_state(gst::State::Null);
     }
 }
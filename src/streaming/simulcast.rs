@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use gstreamer::{self as gst, prelude::*};
+use gstreamer_video as gst_video;
+
+use super::pipeline::RTP_PORT;
+
+/// A quality of the cast, encoded and sent separately from the others
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimulcastLayer {
+    /// Height the stream is scaled to, keeping the aspect ratio
+    pub height: u32,
+    /// Target bitrate in kbit/s
+    pub bitrate: u32,
+}
+
+impl SimulcastLayer {
+    pub const DEFAULTS: [SimulcastLayer; 3] = [
+        SimulcastLayer {
+            height: 1080,
+            bitrate: 4000,
+        },
+        SimulcastLayer {
+            height: 720,
+            bitrate: 1500,
+        },
+        SimulcastLayer {
+            height: 360,
+            bitrate: 400,
+        },
+    ];
+}
+
+impl std::fmt::Display for SimulcastLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}p ({} kbps)", self.height, self.bitrate)
+    }
+}
+
+/// The multiudpsink of every layer, each receiver gets exactly one of them. A receiver keeps
+/// its layer until `switch` moves it, the layers don't follow the quality of its link
+pub(crate) struct LayerSinks {
+    sinks: Vec<gst::Element>,
+    /// Layer of every receiver, by ip
+    receivers: Mutex<HashMap<String, usize>>,
+}
+
+impl LayerSinks {
+    pub fn new(sinks: Vec<gst::Element>) -> Self {
+        Self {
+            sinks,
+            receivers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Starts sending `layer` to a new receiver, returns the layer it actually got
+    pub fn add(&self, ip: &str, layer: usize) -> usize {
+        let layer = layer.min(self.sinks.len() - 1);
+        let previous = self.receivers.lock().unwrap().insert(ip.to_string(), layer);
+        if let Some(previous) = previous {
+            self.sinks[previous].emit_by_name_with_values("remove", &[ip.into(), RTP_PORT.into()]);
+        }
+        self.start_sending(ip, layer);
+        layer
+    }
+
+    /// Moves a receiver to another layer, returns the new layer if it changed
+    pub fn switch(&self, ip: &str, layer: usize) -> Option<usize> {
+        let layer = layer.min(self.sinks.len() - 1);
+        let mut receivers = self.receivers.lock().unwrap();
+        let current = receivers.get_mut(ip)?;
+        if *current == layer {
+            return None;
+        }
+        // the two layers must not overlap on the receiver port
+        self.sinks[*current].emit_by_name_with_values("remove", &[ip.into(), RTP_PORT.into()]);
+        *current = layer;
+        self.start_sending(ip, layer);
+        Some(layer)
+    }
+
+    pub fn remove(&self, ip: &str) {
+        if let Some(layer) = self.receivers.lock().unwrap().remove(ip) {
+            self.sinks[layer].emit_by_name_with_values("remove", &[ip.into(), RTP_PORT.into()]);
+        }
+    }
+
+    /// Number of connected receivers
+    pub fn receivers(&self) -> usize {
+        self.receivers.lock().unwrap().len()
+    }
+
+    fn start_sending(&self, ip: &str, layer: usize) {
+        let sink = &self.sinks[layer];
+        sink.emit_by_name_with_values("add", &[ip.into(), RTP_PORT.into()]);
+        // the receiver can't decode anything before the next keyframe
+        sink.send_event(
+            gst_video::UpstreamForceKeyUnitEvent::builder()
+                .all_headers(true)
+                .build(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `count` layer sinks, `None` if multiudpsink is not installed
+    fn try_layer_sinks(count: usize) -> Option<LayerSinks> {
+        gst::init().unwrap();
+        let sinks: Result<Vec<_>, _> = (0..count)
+            .map(|_| gst::ElementFactory::make("multiudpsink").build())
+            .collect();
+        match sinks {
+            Ok(sinks) => Some(LayerSinks::new(sinks)),
+            Err(_) => {
+                println!("skipped, multiudpsink is not installed");
+                None
+            }
+        }
+    }
+
+    /// The layers sending to `ip`
+    fn layers_of(layers: &LayerSinks, ip: &str) -> Vec<usize> {
+        let client = format!("{}:{}", ip, RTP_PORT);
+        (0..layers.sinks.len())
+            .filter(|&i| {
+                let clients = layers.sinks[i].property::<String>("clients");
+                clients.split(',').any(|c| c == client)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn receivers_get_one_layer() {
+        let Some(layers) = try_layer_sinks(3) else {
+            return;
+        };
+        assert_eq!(layers.add("10.0.0.1", 1), 1);
+        // past the last layer, the lowest quality one
+        assert_eq!(layers.add("10.0.0.2", 7), 2);
+        assert_eq!(layers.receivers(), 2);
+        assert_eq!(layers_of(&layers, "10.0.0.1"), [1]);
+        assert_eq!(layers_of(&layers, "10.0.0.2"), [2]);
+
+        // joining again replaces the previous layer
+        assert_eq!(layers.add("10.0.0.1", 0), 0);
+        assert_eq!(layers.receivers(), 2);
+        assert_eq!(layers_of(&layers, "10.0.0.1"), [0]);
+    }
+
+    #[test]
+    fn switching_moves_only_that_receiver() {
+        let Some(layers) = try_layer_sinks(3) else {
+            return;
+        };
+        layers.add("10.0.0.1", 0);
+        layers.add("10.0.0.2", 0);
+
+        assert_eq!(layers.switch("10.0.0.1", 2), Some(2));
+        assert_eq!(layers_of(&layers, "10.0.0.1"), [2]);
+        assert_eq!(layers_of(&layers, "10.0.0.2"), [0]);
+
+        // same layer or unknown receiver, nothing changes
+        assert_eq!(layers.switch("10.0.0.1", 2), None);
+        assert_eq!(layers.switch("10.0.0.1", 9), None);
+        assert_eq!(layers.switch("10.0.0.3", 1), None);
+        assert!(layers_of(&layers, "10.0.0.3").is_empty());
+    }
+
+    #[test]
+    fn removed_receivers_get_nothing() {
+        let Some(layers) = try_layer_sinks(2) else {
+            return;
+        };
+        layers.add("10.0.0.1", 1);
+        layers.add("10.0.0.2", 1);
+        layers.remove("10.0.0.1");
+        assert_eq!(layers.receivers(), 1);
+        assert!(layers_of(&layers, "10.0.0.1").is_empty());
+        assert_eq!(layers_of(&layers, "10.0.0.2"), [1]);
+
+        // removing twice is harmless
+        layers.remove("10.0.0.1");
+        assert_eq!(layers.receivers(), 1);
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::Local;
+use image::{DynamicImage, ImageFormat};
+use thiserror::Error;
+
+use super::frame::{FrameFormat, VideoFrame};
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("No frame to save yet")]
+    NoFrame,
+
+    #[error("Invalid frame")]
+    InvalidFrame,
+
+    #[error("Image error: {0}")]
+    Image(#[from] image::ImageError),
+
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// File format of the snapshots
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    #[default]
+    Png,
+    Jpeg,
+}
+
+impl SnapshotFormat {
+    pub const ALL: [SnapshotFormat; 2] = [SnapshotFormat::Png, SnapshotFormat::Jpeg];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SnapshotFormat::Png => "png",
+            SnapshotFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+impl std::fmt::Display for SnapshotFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SnapshotFormat::Png => "PNG",
+            SnapshotFormat::Jpeg => "JPEG",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The last frame delivered by a session, kept for `save`
+pub(crate) type LatestFrame = Arc<Mutex<Option<Arc<VideoFrame>>>>;
+
+/// Writes the latest frame in `directory` as `snapshot_<date>_<time>.<ext>`, returns its path
+pub(crate) fn save(
+    latest: &LatestFrame,
+    directory: &Path,
+    format: SnapshotFormat,
+) -> Result<PathBuf, SnapshotError> {
+    let frame = latest
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or(SnapshotError::NoFrame)?;
+
+    fs::create_dir_all(directory)?;
+    // milliseconds keep the periodic snapshots apart
+    let name = Local::now().format("snapshot_%Y%m%d_%H%M%S_%3f");
+    let path = directory.join(format!("{}.{}", name, format.extension()));
+
+    match (frame.format, format) {
+        // already encoded, no need to do it again
+        (FrameFormat::Jpeg, SnapshotFormat::Jpeg) => fs::write(&path, &frame.data)?,
+        _ => {
+            let image = frame.to_rgba_image().ok_or(SnapshotError::InvalidFrame)?;
+            match format {
+                SnapshotFormat::Png => image.save_with_format(&path, ImageFormat::Png)?,
+                // JPEG has no alpha channel
+                SnapshotFormat::Jpeg => DynamicImage::ImageRgba8(image)
+                    .to_rgb8()
+                    .save_with_format(&path, ImageFormat::Jpeg)?,
+            }
+        }
+    }
+
+    Ok(path)
+}
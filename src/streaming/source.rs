@@ -0,0 +1,376 @@
+use gstreamer::{self as gst, prelude::*};
+use gstreamer_app as gst_app;
+use gstreamer_video as gst_video;
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::pipeline::{add_chain, capsfilter, framerate_caps, link_pads, make, BuildError};
+
+/// What the caster sends to the receivers
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureSource {
+    /// A whole monitor (`None` for the whole screen), can be cropped with
+    /// `StreamingServer::capture_resize`
+    Screen { monitor: Option<Monitor> },
+    /// A single window, followed when it moves (X11 only, see `window::list_windows`)
+    Window { id: u64 },
+    /// A screen or window shared through PipeWire, as given by `portal::ScreenCastPortal`.
+    /// This is the only way to capture a Wayland session
+    #[cfg(target_os = "linux")]
+    PipeWire(super::portal::PipeWireStream),
+    /// A webcam, `None` picks the default device
+    Camera { device: Option<String> },
+    /// A video file, played in real time
+    File(PathBuf),
+    /// A `videotestsrc` pattern (e.g. "smpte", "ball"), handy for tests and CI
+    TestPattern(String),
+    /// A sequence of images, each shown for `interval`, looped
+    Slideshow {
+        images: Vec<PathBuf>,
+        interval: Duration,
+    },
+}
+
+impl Default for CaptureSource {
+    fn default() -> Self {
+        CaptureSource::Screen { monitor: None }
+    }
+}
+
+/// A monitor and its geometry in screen coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Monitor {
+    /// Position in the platform's monitor list
+    pub index: u32,
+    /// `HMONITOR` of the monitor on Windows, 0 elsewhere
+    pub handle: u64,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Monitor {
+    /// Top left and bottom right corners (inclusive) as (startx, starty, endx, endy)
+    pub fn bounds(&self) -> (u32, u32, u32, u32) {
+        let x = self.x.max(0) as u32;
+        let y = self.y.max(0) as u32;
+        let endx = x + self.width.saturating_sub(1);
+        let endy = y + self.height.saturating_sub(1);
+        (x, y, endx, endy)
+    }
+}
+
+/// The kind of a `CaptureSource`, without its parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    Screen,
+    Window,
+    PipeWire,
+    Camera,
+    File,
+    TestPattern,
+    Slideshow,
+}
+
+impl SourceKind {
+    pub const ALL: [SourceKind; 7] = [
+        SourceKind::Screen,
+        SourceKind::Window,
+        SourceKind::PipeWire,
+        SourceKind::Camera,
+        SourceKind::File,
+        SourceKind::TestPattern,
+        SourceKind::Slideshow,
+    ];
+}
+
+impl std::fmt::Display for SourceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SourceKind::Screen => "Screen",
+            SourceKind::Window => "Window",
+            SourceKind::PipeWire => "Screen (PipeWire portal)",
+            SourceKind::Camera => "Camera",
+            SourceKind::File => "Video file",
+            SourceKind::TestPattern => "Test pattern",
+            SourceKind::Slideshow => "Slideshow",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl CaptureSource {
+    pub fn kind(&self) -> SourceKind {
+        match self {
+            CaptureSource::Screen { .. } => SourceKind::Screen,
+            CaptureSource::Window { .. } => SourceKind::Window,
+            #[cfg(target_os = "linux")]
+            CaptureSource::PipeWire(_) => SourceKind::PipeWire,
+            CaptureSource::Camera { .. } => SourceKind::Camera,
+            CaptureSource::File(_) => SourceKind::File,
+            CaptureSource::TestPattern(_) => SourceKind::TestPattern,
+            CaptureSource::Slideshow { .. } => SourceKind::Slideshow,
+        }
+    }
+}
+
+/// The capture part of the caster pipeline
+pub(crate) struct CaptureBranch {
+    /// The screen capture element, when capturing the screen
+    pub screen: Option<gst::Element>,
+    /// Crops the captured screen where the source can't do it by itself
+    pub crop: Option<gst::Element>,
+    /// Last element of the branch, producing raw video at `FRAMERATE`
+    pub output: gst::Element,
+}
+
+impl CaptureSource {
+    /// Adds the elements of this source to the pipeline
+    pub(crate) fn build(&self, pipeline: &gst::Pipeline) -> Result<CaptureBranch, BuildError> {
+        match self {
+            CaptureSource::Screen { monitor } => build_screen(pipeline, monitor.as_ref()),
+            CaptureSource::Window { id } => {
+                if !cfg!(target_os = "linux") {
+                    return Err(BuildError::InvalidSource(
+                        "window capture is only supported on Linux".to_string(),
+                    ));
+                }
+                let source = make("ximagesrc")?;
+                source.set_property("xid", *id);
+                source.set_property("use-damage", false);
+                let convert = make("videoconvert")?;
+                add_chain(
+                    pipeline,
+                    &[source, capsfilter(&framerate_caps())?, convert.clone()],
+                )?;
+                Ok(CaptureBranch::new(convert))
+            }
+            #[cfg(target_os = "linux")]
+            CaptureSource::PipeWire(stream) => {
+                let source = make("pipewiresrc")?;
+                source.set_property("path", stream.node_id.to_string());
+                if let Some(fd) = &stream.fd {
+                    // pipewiresrc duplicates the fd, ours stays open as long as the config
+                    source.set_property("fd", fd.as_raw_fd());
+                }
+                // the compositor only sends damaged frames, repeat the last one meanwhile
+                source.set_property("keepalive-time", 1000i32);
+                let mut elements = vec![source];
+                let output = normalize(&mut elements)?;
+                add_chain(pipeline, &elements)?;
+                Ok(CaptureBranch::new(output))
+            }
+            CaptureSource::Camera { device } => {
+                let camera = camera_source(device.as_deref())?;
+                let mut elements = vec![camera];
+                let output = normalize(&mut elements)?;
+                add_chain(pipeline, &elements)?;
+                Ok(CaptureBranch::new(output))
+            }
+            CaptureSource::File(path) => {
+                let uri = gst::glib::filename_to_uri(path, None)
+                    .map_err(|e| BuildError::InvalidSource(e.to_string()))?;
+                let decodebin = make("uridecodebin")?;
+                decodebin.set_property("uri", uri);
+
+                let mut elements = Vec::new();
+                let output = normalize(&mut elements)?;
+                pipeline.add(&decodebin)?;
+                add_chain(pipeline, &elements)?;
+
+                // only the video stream of the file is used
+                let sink = elements[0].static_pad("sink").unwrap();
+                decodebin.connect_pad_added(move |_, pad| {
+                    let is_video = pad
+                        .current_caps()
+                        .and_then(|caps| caps.structure(0).map(|s| s.name().starts_with("video/")))
+                        .unwrap_or(false);
+                    if !is_video || sink.is_linked() {
+                        return;
+                    }
+                    if let Err(e) = link_pads(pad, &sink) {
+                        println!("{}", e);
+                    }
+                });
+
+                Ok(CaptureBranch::new(output))
+            }
+            CaptureSource::TestPattern(pattern) => {
+                let source = make("videotestsrc")?;
+                source.set_property("is-live", true);
+                source.set_property_from_str("pattern", pattern);
+                let caps = capsfilter(&framerate_caps())?;
+                add_chain(pipeline, &[source, caps.clone()])?;
+                Ok(CaptureBranch::new(caps))
+            }
+            CaptureSource::Slideshow { images, interval } => {
+                let appsrc = slideshow_source(images, *interval)?;
+                let scale = make("videoscale")?;
+                let (width, height) = slideshow_size(images)?;
+                let size = capsfilter(
+                    &gst::Caps::builder("video/x-raw")
+                        .field("width", width as i32)
+                        .field("height", height as i32)
+                        .field("pixel-aspect-ratio", gst::Fraction::new(1, 1))
+                        .build(),
+                )?;
+                let mut elements = vec![appsrc.upcast(), make("videoconvert")?, scale, size];
+                let output = normalize(&mut elements)?;
+                add_chain(pipeline, &elements)?;
+                Ok(CaptureBranch::new(output))
+            }
+        }
+    }
+}
+
+impl CaptureBranch {
+    fn new(output: gst::Element) -> Self {
+        Self {
+            screen: None,
+            crop: None,
+            output,
+        }
+    }
+}
+
+/// `source ! capsfilter ! [videocrop !] videoconvert`
+fn build_screen(
+    pipeline: &gst::Pipeline,
+    monitor: Option<&Monitor>,
+) -> Result<CaptureBranch, BuildError> {
+    let source = if cfg!(target_os = "windows") {
+        let source = make("d3d11screencapturesrc")?;
+        source.set_property("show-cursor", true);
+        // the index in winit's list may not be the one in DXGI's
+        if let Some(monitor) = monitor {
+            source.set_property("monitor-handle", monitor.handle);
+        }
+        source
+    } else if cfg!(target_os = "linux") {
+        // the X screen spans every monitor, the monitor is captured as an area
+        let source = make("ximagesrc")?;
+        source.set_property("use-damage", false);
+        if let Some(monitor) = monitor {
+            let (startx, starty, endx, endy) = monitor.bounds();
+            source.set_property("startx", startx);
+            source.set_property("starty", starty);
+            source.set_property("endx", endx);
+            source.set_property("endy", endy);
+        }
+        source
+    } else {
+        let source = make("avfvideosrc")?;
+        source.set_property("capture-screen", true);
+        source.set_property("capture-screen-cursor", true);
+        if let Some(monitor) = monitor {
+            source.set_property("device-index", monitor.index as i32);
+        }
+        source
+    };
+    let crop = if cfg!(target_os = "macos") {
+        Some(make("videocrop")?)
+    } else {
+        None
+    };
+    let convert = make("videoconvert")?;
+
+    let mut elements = vec![source.clone(), capsfilter(&framerate_caps())?];
+    elements.extend(crop.clone());
+    elements.push(convert.clone());
+    add_chain(pipeline, &elements)?;
+
+    Ok(CaptureBranch {
+        screen: Some(source),
+        crop,
+        output: convert,
+    })
+}
+
+/// Webcam element of this platform
+fn camera_source(device: Option<&str>) -> Result<gst::Element, BuildError> {
+    if cfg!(target_os = "windows") {
+        let source = make("mfvideosrc")?;
+        if let Some(device) = device {
+            source.set_property("device-path", device);
+        }
+        Ok(source)
+    } else if cfg!(target_os = "linux") {
+        let source = make("v4l2src")?;
+        if let Some(device) = device {
+            source.set_property("device", device);
+        }
+        Ok(source)
+    } else {
+        let source = make("avfvideosrc")?;
+        if let Some(device) = device {
+            let index = device
+                .parse::<i32>()
+                .map_err(|_| BuildError::InvalidSource(format!("bad device index {}", device)))?;
+            source.set_property("device-index", index);
+        }
+        Ok(source)
+    }
+}
+
+/// Appends `videoconvert ! videorate ! capsfilter`, so that any source outputs `FRAMERATE`
+fn normalize(elements: &mut Vec<gst::Element>) -> Result<gst::Element, BuildError> {
+    let caps = capsfilter(&framerate_caps())?;
+    elements.push(make("videoconvert")?);
+    elements.push(make("videorate")?);
+    elements.push(caps.clone());
+    Ok(caps)
+}
+
+/// Size of the first image, every image of the slideshow is scaled to it
+fn slideshow_size(images: &[PathBuf]) -> Result<(u32, u32), BuildError> {
+    let first = images
+        .first()
+        .ok_or_else(|| BuildError::InvalidSource("slideshow without images".to_string()))?;
+    image::image_dimensions(first)
+        .map_err(|e| BuildError::InvalidSource(format!("{}: {}", first.display(), e)))
+}
+
+/// An appsrc pushing the decoded images, one every `interval`
+fn slideshow_source(images: &[PathBuf], interval: Duration) -> Result<gst_app::AppSrc, BuildError> {
+    let slides = images
+        .iter()
+        .map(|path| {
+            let image = image::open(path)
+                .map_err(|e| BuildError::InvalidSource(format!("{}: {}", path.display(), e)))?
+                .to_rgba8();
+            let caps = gst_video::VideoCapsBuilder::new()
+                .format(gst_video::VideoFormat::Rgba)
+                .width(image.width() as i32)
+                .height(image.height() as i32)
+                .framerate(gst::Fraction::new(0, 1))
+                .build();
+            Ok((caps, gst::Buffer::from_mut_slice(image.into_raw())))
+        })
+        .collect::<Result<Vec<_>, BuildError>>()?;
+
+    let appsrc = gst_app::AppSrc::builder().format(gst::Format::Time).build();
+
+    let interval = gst::ClockTime::from_nseconds(interval.as_nanos() as u64);
+    let mut index = 0u64;
+    appsrc.set_callbacks(
+        gst_app::AppSrcCallbacks::builder()
+            .need_data(move |appsrc, _| {
+                let (caps, buffer) = &slides[(index % slides.len() as u64) as usize];
+                let mut buffer = buffer.copy();
+                {
+                    let buffer = buffer.get_mut().unwrap();
+                    buffer.set_pts(interval * index);
+                    buffer.set_duration(interval);
+                }
+                appsrc.set_caps(Some(caps));
+                let _ = appsrc.push_buffer(buffer);
+                index += 1;
+            })
+            .build(),
+    );
+
+    Ok(appsrc)
+}
@@ -0,0 +1,176 @@
+use std::path::PathBuf;
+
+use gstreamer::{self as gst, prelude::*};
+
+use super::pipeline::{make, BuildError};
+
+/// Distance of the watermarks from the edges of the frame, in pixels
+const MARGIN: i32 = 25;
+/// strftime format of the clock
+const CLOCK_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Corner of the frame where a watermark is drawn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+impl WatermarkPosition {
+    pub const ALL: [WatermarkPosition; 4] = [
+        WatermarkPosition::TopLeft,
+        WatermarkPosition::TopRight,
+        WatermarkPosition::BottomLeft,
+        WatermarkPosition::BottomRight,
+    ];
+
+    /// `halignment` and `valignment` of the text overlays
+    fn alignment(&self) -> (&'static str, &'static str) {
+        match self {
+            WatermarkPosition::TopLeft => ("left", "top"),
+            WatermarkPosition::TopRight => ("right", "top"),
+            WatermarkPosition::BottomLeft => ("left", "bottom"),
+            WatermarkPosition::BottomRight => ("right", "bottom"),
+        }
+    }
+
+    /// Offsets of the image overlay, negative ones are from the right and bottom edges
+    fn offset(&self) -> (i32, i32) {
+        match self {
+            WatermarkPosition::TopLeft => (MARGIN, MARGIN),
+            WatermarkPosition::TopRight => (-MARGIN, MARGIN),
+            WatermarkPosition::BottomLeft => (MARGIN, -MARGIN),
+            WatermarkPosition::BottomRight => (-MARGIN, -MARGIN),
+        }
+    }
+}
+
+impl std::fmt::Display for WatermarkPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            WatermarkPosition::TopLeft => "Top left",
+            WatermarkPosition::TopRight => "Top right",
+            WatermarkPosition::BottomLeft => "Bottom left",
+            WatermarkPosition::BottomRight => "Bottom right",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The kinds of watermark, each one needs its own element
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatermarkKind {
+    Text,
+    Clock,
+    Image,
+}
+
+impl WatermarkKind {
+    pub const ALL: [WatermarkKind; 3] = [
+        WatermarkKind::Text,
+        WatermarkKind::Clock,
+        WatermarkKind::Image,
+    ];
+}
+
+impl std::fmt::Display for WatermarkKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            WatermarkKind::Text => "Text",
+            WatermarkKind::Clock => "Clock",
+            WatermarkKind::Image => "Logo",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatermarkContent {
+    /// e.g. the presenter name
+    Text(String),
+    /// Current date and time
+    Clock,
+    /// An image file, e.g. a logo
+    Image(PathBuf),
+}
+
+impl WatermarkContent {
+    pub fn kind(&self) -> WatermarkKind {
+        match self {
+            WatermarkContent::Text(_) => WatermarkKind::Text,
+            WatermarkContent::Clock => WatermarkKind::Clock,
+            WatermarkContent::Image(_) => WatermarkKind::Image,
+        }
+    }
+}
+
+/// Text or image stamped on every frame of the cast
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    pub content: WatermarkContent,
+    pub position: WatermarkPosition,
+    /// From 0 (invisible) to 1
+    pub opacity: f64,
+}
+
+impl Watermark {
+    pub fn new(content: WatermarkContent) -> Self {
+        Self {
+            content,
+            position: WatermarkPosition::default(),
+            opacity: 0.7,
+        }
+    }
+
+    pub(crate) fn element(&self) -> Result<gst::Element, BuildError> {
+        let opacity = self.opacity.clamp(0.0, 1.0);
+        let element = match &self.content {
+            WatermarkContent::Text(text) => {
+                let overlay = make("textoverlay")?;
+                overlay.set_property("text", text.as_str());
+                overlay
+            }
+            WatermarkContent::Clock => {
+                let overlay = make("clockoverlay")?;
+                overlay.set_property("time-format", CLOCK_FORMAT);
+                overlay
+            }
+            WatermarkContent::Image(path) => {
+                let overlay = make("gdkpixbufoverlay")?;
+                let (x, y) = self.position.offset();
+                overlay.set_property("location", &*path.to_string_lossy());
+                overlay.set_property("offset-x", x);
+                overlay.set_property("offset-y", y);
+                overlay.set_property("alpha", opacity);
+                return Ok(overlay);
+            }
+        };
+
+        let (halignment, valignment) = self.position.alignment();
+        element.set_property_from_str("halignment", halignment);
+        element.set_property_from_str("valignment", valignment);
+        // colors are ARGB, white text with a black outline
+        let alpha = ((opacity * 255.0).round() as u32) << 24;
+        element.set_property("color", alpha | 0x00ff_ffff);
+        element.set_property("outline-color", alpha);
+        Ok(element)
+    }
+}
+
+/// The watermarks of a cast
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WatermarkSettings {
+    pub watermarks: Vec<Watermark>,
+    /// Stamp only the recordings, receivers get the clean stream
+    pub recordings_only: bool,
+}
+
+impl WatermarkSettings {
+    /// One element per watermark, to be chained in order
+    pub(crate) fn elements(&self) -> Result<Vec<gst::Element>, BuildError> {
+        self.watermarks.iter().map(Watermark::element).collect()
+    }
+}
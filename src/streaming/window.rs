@@ -0,0 +1,93 @@
+use thiserror::Error;
+use x11rb::connection::Connection;
+use x11rb::errors::{ConnectError, ConnectionError, ReplyError};
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt, Window};
+use x11rb::rust_connection::RustConnection;
+
+#[derive(Error, Debug)]
+pub enum WindowListError {
+    #[error("Can't connect to the X server: {0}")]
+    Connect(#[from] ConnectError),
+
+    #[error("X connection error: {0}")]
+    Connection(#[from] ConnectionError),
+
+    #[error("X request error: {0}")]
+    Reply(#[from] ReplyError),
+}
+
+/// A top-level window that can be cast with `CaptureSource::Window`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowInfo {
+    /// X11 window id
+    pub id: u64,
+    pub title: String,
+    /// Position of the top left corner on the screen
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Lists the top-level windows managed by the window manager
+pub fn list_windows() -> Result<Vec<WindowInfo>, WindowListError> {
+    let (conn, screen_num) = x11rb::connect(None)?;
+    let root = conn.setup().roots[screen_num].root;
+
+    let client_list = intern(&conn, b"_NET_CLIENT_LIST")?;
+    let net_wm_name = intern(&conn, b"_NET_WM_NAME")?;
+    let utf8_string = intern(&conn, b"UTF8_STRING")?;
+
+    let list = conn
+        .get_property(false, root, client_list, AtomEnum::WINDOW, 0, u32::MAX)?
+        .reply()?;
+    let ids: Vec<Window> = list.value32().map(|v| v.collect()).unwrap_or_default();
+
+    let mut windows = Vec::with_capacity(ids.len());
+    for id in ids {
+        match window_info(&conn, root, id, net_wm_name, utf8_string) {
+            Ok(window) => windows.push(window),
+            // the window was closed after the list was read
+            Err(WindowListError::Reply(ReplyError::X11Error(_))) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(windows)
+}
+
+fn window_info(
+    conn: &RustConnection,
+    root: Window,
+    id: Window,
+    net_wm_name: u32,
+    utf8_string: u32,
+) -> Result<WindowInfo, WindowListError> {
+    let mut title = conn
+        .get_property(false, id, net_wm_name, utf8_string, 0, u32::MAX)?
+        .reply()?
+        .value;
+    if title.is_empty() {
+        // windows not following EWMH only have the legacy name
+        title = conn
+            .get_property(false, id, AtomEnum::WM_NAME, AtomEnum::STRING, 0, u32::MAX)?
+            .reply()?
+            .value;
+    }
+
+    let geometry = conn.get_geometry(id)?.reply()?;
+    let position = conn.translate_coordinates(id, root, 0, 0)?.reply()?;
+
+    Ok(WindowInfo {
+        id: id as u64,
+        title: String::from_utf8_lossy(&title).into_owned(),
+        x: position.dst_x as i32,
+        y: position.dst_y as i32,
+        width: geometry.width as u32,
+        height: geometry.height as u32,
+    })
+}
+
+fn intern(conn: &RustConnection, name: &[u8]) -> Result<u32, WindowListError> {
+    Ok(conn.intern_atom(false, name)?.reply()?.atom)
+}
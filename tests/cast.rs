@@ -0,0 +1,57 @@
+use std::time::{Duration, Instant};
+
+use gstreamer as gst;
+use rust_streamer::streaming::event::StreamingEvent;
+use rust_streamer::streaming::frame::FrameFormat;
+use rust_streamer::streaming::pipeline::{BuildError, ServerConfig, VideoEncoder};
+use rust_streamer::streaming::server::{StreamingServer, StreamingServerError};
+use rust_streamer::streaming::source::CaptureSource;
+
+/// Longest wait for the first frames, the pipeline needs a moment to start
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[test]
+fn test_pattern_frames_reach_the_subscribers() {
+    gst::init().unwrap();
+    let Some(encoder) = VideoEncoder::ALL
+        .into_iter()
+        .find(|encoder| gst::ElementFactory::find(encoder.element_name()).is_some())
+    else {
+        println!("skipped, no H264 encoder is installed");
+        return;
+    };
+
+    let server = match StreamingServer::new(ServerConfig {
+        frame_format: FrameFormat::Rgba,
+        source: CaptureSource::TestPattern("ball".to_string()),
+        encoder,
+        ..Default::default()
+    }) {
+        Ok(server) => server,
+        Err(StreamingServerError::PipelineBuildError(BuildError::MissingElement(element))) => {
+            println!("skipped, {} is not installed", element);
+            return;
+        }
+        Err(e) => panic!("{}", e),
+    };
+    let events = server.subscribe();
+    server.start().unwrap();
+
+    let deadline = Instant::now() + TIMEOUT;
+    let mut frames = Vec::new();
+    while frames.len() < 3 {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match events.recv_timeout(left) {
+            Ok(StreamingEvent::FrameReady(frame)) => frames.push(frame),
+            Ok(StreamingEvent::Error(e)) => panic!("{}", e),
+            Ok(_) => {}
+            Err(_) => panic!("only {} frames in {:?}", frames.len(), TIMEOUT),
+        }
+    }
+
+    for frame in frames {
+        assert_eq!(frame.format, FrameFormat::Rgba);
+        assert!(frame.width > 0 && frame.height > 0);
+        assert!(frame.data.len() >= frame.stride * frame.height as usize);
+    }
+}